@@ -3,6 +3,7 @@ use criterion::{
     SamplingMode,
 };
 use dev_utils::*;
+use std::path::PathBuf;
 use std::process::Command;
 use std::time::Duration;
 
@@ -27,7 +28,7 @@ fn fcp_benchmark(mut group: BenchmarkGroup<WallTime>, fixture_file: &str) {
                 || {
                     remove(&dest_path);
                     let mut command = Command::new("cp");
-                    command.args(&["-R", source, dest]);
+                    command.args(["-R", source, dest]);
                     command
                 },
                 run_command,
@@ -42,7 +43,7 @@ fn fcp_benchmark(mut group: BenchmarkGroup<WallTime>, fixture_file: &str) {
                 || {
                     remove(&dest_path);
                     let mut command = Command::new(executable_path);
-                    command.args(&[source, dest]);
+                    command.args([source, dest]);
                     command
                 },
                 run_command,
@@ -68,5 +69,48 @@ fn large_files_benchmark(c: &mut Criterion) {
     fcp_benchmark(group, "large_files.json");
 }
 
-criterion_group!(benches, linux_benchmark, large_files_benchmark);
+fn buffer_size_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Buffer Size");
+    group.sampling_mode(SamplingMode::Flat);
+    group.warm_up_time(Duration::from_secs(60));
+    group.sample_size(50);
+    initialize();
+    hydrate_fixture("linux.json");
+    let source_path = HYDRATED_DIR.join("linux");
+    // Copying from the fixtures to a tmpfs generally keeps copies from being made within the
+    // kernel, so that the contents of every file are copied through the buffer.
+    let dest_path = PathBuf::from("/dev/shm/fcp_buffer_size");
+    let (source, dest) = (source_path.to_str().unwrap(), dest_path.to_str().unwrap());
+    let executable_path = fcp_executable_path();
+    let executable_path = executable_path.to_str().unwrap();
+    for buffer_size in [None, Some("4K"), Some("128K"), Some("4M")] {
+        group.bench_with_input(
+            BenchmarkId::new("FCP", buffer_size.unwrap_or("Default")),
+            &(source, dest),
+            |b, (source, dest)| {
+                b.iter_with_setup(
+                    || {
+                        remove(&dest_path);
+                        let mut command = Command::new(executable_path);
+                        if let Some(buffer_size) = buffer_size {
+                            command.arg(format!("--buffer-size={}", buffer_size));
+                        }
+                        command.args([source, dest]);
+                        command
+                    },
+                    run_command,
+                )
+            },
+        );
+    }
+    group.finish();
+    remove(&dest_path);
+}
+
+criterion_group!(
+    benches,
+    linux_benchmark,
+    large_files_benchmark,
+    buffer_size_benchmark
+);
 criterion_main!(benches);
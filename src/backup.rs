@@ -0,0 +1,56 @@
+//! Backing up existing destinations before they're overwritten, with the same naming schemes as
+//! GNU coreutils.
+
+use crate::filesystem::{self as fs, DestPath, Error};
+use crate::options::Backup;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Rename the file at `dest` (if there is one, and it isn't a directory) to the name of its backup
+/// according to `control`.
+pub fn back_up(dest: &DestPath, control: Backup) -> Result<(), Error> {
+    match fs::symlink_metadata(dest) {
+        Ok(metadata) if !metadata.is_dir() => fs::rename(dest, backup_path(dest, control)?),
+        _ => Ok(()),
+    }
+}
+
+fn backup_path(dest: &DestPath, control: Backup) -> Result<DestPath, Error> {
+    let numbered = match control {
+        Backup::None => unreachable!("backups are disabled"),
+        Backup::Simple => None,
+        Backup::Numbered => Some(latest_backup(dest)?.unwrap_or(0) + 1),
+        Backup::Existing => latest_backup(dest)?.map(|latest| latest + 1),
+    };
+    let mut name = OsString::from(dest.file_name().unwrap_or_default());
+    match numbered {
+        Some(number) => name.push(format!(".~{}~", number)),
+        None => name.push("~"),
+    }
+    Ok(dest.with_file_name(name))
+}
+
+/// The number of the latest numbered backup of `dest` (i.e. of the form `dest.~N~`), if any.
+fn latest_backup(dest: &Path) -> Result<Option<u64>, Error> {
+    let (parent, file_name) = match (dest.parent(), dest.file_name()) {
+        (Some(parent), Some(file_name)) => (parent, file_name.to_string_lossy()),
+        _ => return Ok(None),
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let prefix = format!("{}.~", file_name);
+    let mut latest = None;
+    for entry in fs::read_dir(parent)? {
+        let name = entry?.file_name();
+        let number = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|suffix| suffix.strip_suffix('~'))
+            .and_then(|number| number.parse().ok());
+        latest = latest.max(number);
+    }
+    Ok(latest)
+}
@@ -2,18 +2,133 @@
 //! them to cover the full gamut of POSIX file types, and wrapping them in order to improve the
 //! usefulness of error messages by providing additional context.
 
-use nix::sys::stat::Mode;
-use nix::unistd;
-use std::convert::TryInto;
+use nix::dir::Dir;
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+#[cfg(target_os = "linux")]
+use nix::fcntl::{FallocateFlags, PosixFadviseAdvice};
+use nix::libc::{self, timespec};
+use nix::sys::mman::{self, MapFlags, MmapAdvise, ProtFlags};
+#[cfg(target_os = "linux")]
+use nix::sys::sendfile;
+use nix::sys::stat::{self, FchmodatFlags, Mode, SFlag, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+#[cfg(target_os = "linux")]
+use nix::unistd::Whence;
+use nix::unistd::{self, FchownatFlags, Gid, LinkatFlags, Uid, UnlinkatFlags};
+#[cfg(target_os = "linux")]
+use std::cell::RefCell;
+use std::cmp;
+use std::convert::{Infallible, TryInto};
 use std::error::Error as BaseError;
+use std::ffi::{CStr, CString, FromBytesWithNulError, NulError, OsStr, OsString};
 use std::fmt;
-use std::fs::{self, DirBuilder, File, Metadata, OpenOptions, Permissions, ReadDir};
-use std::os::unix::fs::{self as unix, DirBuilderExt, FileTypeExt, OpenOptionsExt, PermissionsExt};
-use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::fs::OpenOptions;
+use std::fs::{self, File, Metadata, Permissions, ReadDir};
+use std::io;
+use std::io::{prelude::*, SeekFrom};
+use std::num::TryFromIntError;
+use std::ops::{Deref, Range};
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use xxhash_rust::xxh3::Xxh3;
+
+/// The broad category of an [`Error`], allowing callers to respond to particular failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    /// An operation (such as a rename or hard link) which can't span filesystems was attempted
+    /// across two of them
+    CrossDevice,
+    /// The arguments given for an operation were invalid
+    InvalidInput,
+    /// Any I/O error not covered by a more specific kind
+    Io,
+    /// The contents of a copy differ from those of its source
+    ChecksumMismatch,
+    /// An error not originating from an I/O operation
+    Other,
+}
+
+impl ErrorKind {
+    fn from_errno(errno: i32) -> Self {
+        match errno {
+            libc::ENOENT => ErrorKind::NotFound,
+            libc::EACCES | libc::EPERM => ErrorKind::PermissionDenied,
+            libc::EEXIST => ErrorKind::AlreadyExists,
+            libc::EXDEV => ErrorKind::CrossDevice,
+            _ => ErrorKind::Io,
+        }
+    }
+}
+
+// An underlying error which an `Error` can be created from
+trait Cause: BaseError + Send + Sync + Sized + 'static {
+    fn error_kind(&self) -> ErrorKind;
+
+    // The error to retain as the source of the `Error`
+    fn into_source(self) -> Box<dyn BaseError + Send + Sync> {
+        Box::new(self)
+    }
+}
+
+impl Cause for io::Error {
+    fn error_kind(&self) -> ErrorKind {
+        match (self.raw_os_error(), self.kind()) {
+            (Some(errno), _) => ErrorKind::from_errno(errno),
+            (None, io::ErrorKind::NotFound) => ErrorKind::NotFound,
+            (None, io::ErrorKind::PermissionDenied) => ErrorKind::PermissionDenied,
+            (None, io::ErrorKind::AlreadyExists) => ErrorKind::AlreadyExists,
+            (None, _) => ErrorKind::Io,
+        }
+    }
+}
+
+impl Cause for nix::Error {
+    fn error_kind(&self) -> ErrorKind {
+        self.as_errno().map_or(ErrorKind::Other, |errno| {
+            ErrorKind::from_errno(errno as i32)
+        })
+    }
+
+    // Converted into an I/O error where possible, so that its error number can be retrieved
+    fn into_source(self) -> Box<dyn BaseError + Send + Sync> {
+        match self.as_errno() {
+            Some(errno) => Box::new(io::Error::from_raw_os_error(errno as i32)),
+            None => Box::new(self),
+        }
+    }
+}
+
+macro_rules! impl_cause_other {
+    ($($type:ty),*) => {
+        $(impl Cause for $type {
+            fn error_kind(&self) -> ErrorKind {
+                ErrorKind::Other
+            }
+        })*
+    };
+}
+
+impl_cause_other!(NulError, FromBytesWithNulError, TryFromIntError);
 
 #[derive(Debug)]
 pub struct Error {
+    kind: ErrorKind,
+    path: Option<PathBuf>,
     message: String,
+    /// The underlying error which caused this one, if any
+    source: Option<Box<dyn BaseError + Send + Sync>>,
 }
 
 impl fmt::Display for Error {
@@ -22,115 +137,2169 @@ impl fmt::Display for Error {
     }
 }
 
-impl<T: BaseError> From<T> for Error {
-    fn from(other: T) -> Self {
-        Error {
-            message: other.to_string(),
-        }
+impl BaseError for Error {
+    fn source(&self) -> Option<&(dyn BaseError + 'static)> {
+        self.source.as_deref().map(|err| err as _)
+    }
+}
+
+macro_rules! impl_from_cause {
+    ($($type:ty),*) => {
+        $(impl From<$type> for Error {
+            fn from(other: $type) -> Self {
+                Error {
+                    kind: other.error_kind(),
+                    path: None,
+                    message: other.to_string(),
+                    source: Some(other.into_source()),
+                }
+            }
+        })*
+    };
+}
+
+impl_from_cause!(io::Error, nix::Error, TryFromIntError);
+
+// Integer conversions which can't fail on some platforms (e.g. of `mode_t`) still use `?`
+impl From<Infallible> for Error {
+    fn from(never: Infallible) -> Self {
+        match never {}
     }
 }
 
 impl Error {
     pub fn new(message: String) -> Self {
-        Error { message }
+        Error {
+            kind: ErrorKind::Other,
+            path: None,
+            message,
+            source: None,
+        }
+    }
+
+    pub fn invalid_input(message: String) -> Self {
+        Error {
+            kind: ErrorKind::InvalidInput,
+            ..Error::new(message)
+        }
+    }
+
+    /// An error for the existing file at `path` being a directory (if `directory` is set) where the
+    /// file to be copied over it isn't, or vice versa.
+    pub fn type_conflict(path: &Path, directory: bool) -> Self {
+        let message = if directory {
+            format!(
+                "cannot overwrite directory '{}' with non-directory",
+                path.display()
+            )
+        } else {
+            format!(
+                "cannot overwrite non-directory '{}' with directory",
+                path.display()
+            )
+        };
+        Error {
+            kind: ErrorKind::AlreadyExists,
+            path: Some(path.to_path_buf()),
+            message,
+            source: None,
+        }
+    }
+
+    // Create an error caused by `cause` while operating on `path`
+    fn with_cause(cause: impl Cause, path: &Path, message: String) -> Self {
+        Error {
+            kind: cause.error_kind(),
+            path: Some(path.to_path_buf()),
+            message,
+            source: Some(cause.into_source()),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The error number of the underlying I/O error, if there is one.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.source
+            .as_deref()
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .and_then(io::Error::raw_os_error)
+    }
+
+    /// The path of the file this error concerns (or the first such file, if there are several).
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
     }
 }
 
+macro_rules! make_error_message {
+    ($path:ident) => {
+        |err| {
+            let message = format!("{}: {}", $path.display(), err);
+            Error::with_cause(err, &$path, message)
+        }
+    };
+    ($source:ident, $dest:ident) => {
+        |err| {
+            let message = format!("{}, {}: {}", $source.display(), $dest.display(), err);
+            Error::with_cause(err, &$source, message)
+        }
+    };
+}
+
 macro_rules! wrap {
     ($namespace:ident, $function:ident, $payload:ty) => {
         pub fn $function<P: AsRef<Path>>(path: P) -> Result<$payload, Error> {
-            $namespace::$function(path.as_ref())
-                .map_err(|err| Error::new(format!("{}: {}", path.as_ref().display(), err)))
+            let path = path.as_ref();
+            $namespace::$function(path).map_err(make_error_message!(path))
         }
     };
 }
 
-macro_rules! wrap2 {
-    ($function:ident, $namespace:ident, $payload:ty) => {
-        pub fn $function<P: AsRef<Path>, Q: AsRef<Path>>(
-            source: P,
-            dest: Q,
-        ) -> Result<$payload, Error> {
-            let (source, dest) = (source.as_ref(), dest.as_ref());
-            $namespace::$function(source, dest).map_err(|err| {
-                Error::new(format!("{}, {}: {}", source.display(), dest.display(), err))
+wrap!(fs, metadata, Metadata);
+wrap!(fs, read_link, PathBuf);
+wrap!(fs, canonicalize, PathBuf);
+wrap!(fs, read, Vec<u8>);
+wrap!(fs, read_dir, ReadDir);
+
+pub fn symlink_metadata<P: AsPath>(path: P) -> Result<Metadata, Error> {
+    let path = reach(&path);
+    let at = at(path).map_err(make_error_message!(path))?;
+    match at.fd() {
+        // There's no `fstatat` to `Metadata`, but (on Linux) a symlink can be opened itself
+        #[cfg(target_os = "linux")]
+        Some(_) => {
+            open_in(&at, OFlag::O_PATH | OFlag::O_NOFOLLOW, 0).and_then(|file| file.metadata())
+        }
+        _ => fs::symlink_metadata(path.path),
+    }
+    .map_err(make_error_message!(path))
+}
+
+pub fn open<P: AsPath>(path: P) -> Result<File, Error> {
+    let path = reach(&path);
+    open_at(path, OFlag::O_RDONLY, 0).map_err(make_error_message!(path))
+}
+
+pub fn remove_file<P: AsPath>(path: P) -> Result<(), Error> {
+    let path = reach(&path);
+    at(path)
+        .and_then(|at| {
+            unistd::unlinkat(at.fd(), at.name, UnlinkatFlags::NoRemoveDir).map_err(io_error)
+        })
+        .map_err(make_error_message!(path))
+}
+
+pub fn remove_dir_all<P: AsPath>(path: P) -> Result<(), Error> {
+    let path = reach(&path);
+    at(path)
+        .and_then(|at| match at.fd() {
+            Some(directory) => remove_dir_all_at(directory, at.name).map_err(io_error),
+            None => fs::remove_dir_all(path.path),
+        })
+        .map_err(make_error_message!(path))
+}
+
+pub fn symlink<P: AsRef<Path>, Q: AsPath>(source: P, dest: Q) -> Result<(), Error> {
+    let source = source.as_ref();
+    copy_symlink(source, source, dest)
+}
+
+/// Create `dest` as a symlink to `target`, as the copy of `source` (e.g. the symlink it's read
+/// from), which any error is reported in terms of rather than `target`.
+pub fn copy_symlink<P: AsRef<Path>, Q: AsRef<Path>, R: AsPath>(
+    source: P,
+    target: Q,
+    dest: R,
+) -> Result<(), Error> {
+    let (source, target, dest) = (source.as_ref(), target.as_ref(), reach(&dest));
+    at(dest)
+        .and_then(|at| unistd::symlinkat(target, at.fd(), at.name).map_err(io_error))
+        .map_err(make_error_message!(source, dest))
+}
+
+pub fn hard_link<P: AsPath, Q: AsPath>(source: P, dest: Q) -> Result<(), Error> {
+    link_at(reach(&source), reach(&dest), LinkatFlags::NoSymlinkFollow)
+}
+
+/// Create `dest` as a hard link to `source`, or to the file it points to if it's a symlink.
+pub fn link<P: AsPath, Q: AsPath>(source: P, dest: Q) -> Result<(), Error> {
+    link_at(reach(&source), reach(&dest), LinkatFlags::SymlinkFollow)
+}
+
+fn link_at(source: Reach, dest: Reach, flags: LinkatFlags) -> Result<(), Error> {
+    at(source)
+        .and_then(|source_at| {
+            let dest_at = at(dest)?;
+            retry_on_eintr(|| {
+                unistd::linkat(
+                    source_at.fd(),
+                    source_at.name,
+                    dest_at.fd(),
+                    dest_at.name,
+                    flags,
+                )
             })
+            .map_err(io_error)
+        })
+        .map_err(make_error_message!(source, dest))
+}
+
+/// Flush the contents and metadata of the regular file or directory `path` to the underlying
+/// storage device, so that they persist through a crash.
+pub fn sync<P: AsPath>(path: P) -> Result<(), Error> {
+    let path = reach(&path);
+    // Files which can't be read (such as those with modes of 0o200) can still be synced through a
+    // descriptor opened for writing
+    open_at(path, OFlag::O_RDONLY, 0)
+        .or_else(|_| open_at(path, OFlag::O_WRONLY, 0))
+        .and_then(|file| file.sync_all())
+        .map_err(make_error_message!(path))
+}
+
+/// Advise the kernel that the contents of `path` won't be needed again, so that they're dropped
+/// from the page cache (once written back, for those which are yet to be). This does nothing on
+/// platforms other than Linux.
+pub fn drop_cache<P: AsPath>(path: P) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let path = reach(&path);
+        let file = open_at(path, OFlag::O_RDONLY, 0)
+            .or_else(|_| open_at(path, OFlag::O_WRONLY, 0))
+            .map_err(make_error_message!(path))?;
+        fcntl::posix_fadvise(
+            file.as_raw_fd(),
+            0,
+            0,
+            PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+        )
+        .map_err(io_error)
+        .map_err(make_error_message!(path))?;
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Rename `source` to `dest`. Where they're on different filesystems, a regular file or symlink
+/// `source` is instead moved by copying it to `dest` (replacing any existing file there) and then
+/// removing it, whereas any other kind of file fails to be renamed with [`ErrorKind::CrossDevice`].
+pub fn rename<P: AsPath, Q: AsPath>(source: P, dest: Q) -> Result<(), Error> {
+    let (source, dest) = (reach(&source), reach(&dest));
+    let renamed = at(source).and_then(|source_at| {
+        let dest_at = at(dest)?;
+        fcntl::renameat(source_at.fd(), source_at.name, dest_at.fd(), dest_at.name)
+            .map_err(io_error)
+    });
+    match renamed {
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+            let metadata = symlink_metadata(source)?;
+            let file_type = metadata.file_type();
+            if !file_type.is_file() && !file_type.is_symlink() {
+                return Err(make_error_message!(source, dest)(err));
+            }
+            match remove_file(dest) {
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                result => result?,
+            }
+            if file_type.is_symlink() {
+                copy_symlink(source, read_link(source)?, dest)?;
+            } else {
+                let mode = metadata.permissions().mode();
+                copy(source, dest, mode, None, None, None, false, true, |_| {})?;
+            }
+            set_timestamps(dest, &metadata)?;
+            remove_file(source)
         }
+        result => result.map_err(make_error_message!(source, dest)),
+    }
+}
+
+/// The flags with which the directories leading to a file beneath a [`SecureDirectory`] are opened
+/// to walk down through: merely to look up the entries within them (which needs no permission to
+/// read them, on Linux), and failing rather than following any which is a symlink.
+#[cfg(target_os = "linux")]
+const WALK_FLAGS: OFlag = OFlag::from_bits_truncate(
+    libc::O_PATH | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+);
+#[cfg(not(target_os = "linux"))]
+const WALK_FLAGS: OFlag = OFlag::from_bits_truncate(
+    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+);
+
+/// A directory held open, through which the files within it are reached (as [`DestPath`]s from
+/// [`SecureDirectory::entry`]) and operated on relative to it (with `openat`, `mkdirat`, and so on)
+/// rather than by their paths. Each directory beneath the root one (whose own path is trusted) is
+/// itself opened through the directory containing it without following symlinks, so replacing any
+/// of them with a symlink (e.g. in a world-writable destination) can't redirect anything outside of
+/// the root.
+pub struct SecureDirectory {
+    file: Arc<File>,
+    path: PathBuf,
+    /// The directory at the root of the tree this is within, or `None` if this is it
+    root: Option<Arc<SecureDirectory>>,
+}
+
+impl SecureDirectory {
+    /// Open the directory `path` as the root of a tree, failing rather than following it if it's a
+    /// symlink.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, Error> {
+        let path = path.as_ref();
+        let fd = retry_on_eintr(|| fcntl::open(path, WALK_FLAGS, Mode::empty()))
+            .map_err(io_error)
+            .map_err(make_error_message!(path))?;
+        Ok(Arc::new(SecureDirectory {
+            file: Arc::new(unsafe { File::from_raw_fd(fd) }),
+            path: path.to_path_buf(),
+            root: None,
+        }))
+    }
+
+    /// Open the directory `dest` within the tree rooted at this directory, through the directory
+    /// containing it if that's held open, and otherwise by walking down to it from the root (or
+    /// this directory itself, if `dest` is the root).
+    pub fn open_within(self: &Arc<Self>, dest: &DestPath) -> Result<Arc<Self>, Error> {
+        if dest.path == self.path {
+            return Ok(Arc::clone(self));
+        }
+        let file = open_at(reach(dest), WALK_FLAGS, 0).map_err(make_error_message!(dest))?;
+        Ok(Arc::new(SecureDirectory {
+            file: Arc::new(file),
+            path: dest.path.clone(),
+            root: Some(self.root.clone().unwrap_or_else(|| Arc::clone(self))),
+        }))
+    }
+
+    /// The entry `name` of this directory, to be reached through it.
+    pub fn entry(self: &Arc<Self>, name: &OsStr) -> DestPath {
+        DestPath {
+            path: self.path.join(name),
+            root: Some(self.root.clone().unwrap_or_else(|| Arc::clone(self))),
+            directory: Some(Arc::clone(self)),
+        }
+    }
+}
+
+/// The path of a file (which may not exist yet) to be operated on through the directory
+/// containing it, where that's a [`SecureDirectory`], or else by its path.
+#[derive(Clone)]
+pub struct DestPath {
+    path: PathBuf,
+    /// The directory at the root of the tree the file is within, if it's to be reached through one
+    root: Option<Arc<SecureDirectory>>,
+    /// The directory containing the file, if that's held open (or else the file is reached by
+    /// walking down to it from `root`)
+    directory: Option<Arc<SecureDirectory>>,
+}
+
+impl DestPath {
+    /// A file to be reached by its path alone.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        DestPath {
+            path: path.into(),
+            root: None,
+            directory: None,
+        }
+    }
+
+    /// The same file, without holding the directory containing it open (e.g. to be reached some
+    /// time later, when holding every such directory open at once could exhaust descriptors).
+    pub fn detach(&self) -> Self {
+        DestPath {
+            path: self.path.clone(),
+            root: self.root.clone(),
+            directory: None,
+        }
+    }
+
+    /// The file `name` alongside this one, within the same directory.
+    pub fn with_file_name<S: AsRef<OsStr>>(&self, name: S) -> Self {
+        DestPath {
+            path: self.path.with_file_name(name),
+            root: self.root.clone(),
+            directory: self.directory.clone(),
+        }
+    }
+}
+
+impl Deref for DestPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for DestPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A path to a file, which the functions operating on destinations accept either as a plain path
+/// or as a [`DestPath`] (to be reached through its directory).
+pub trait AsPath {
+    fn as_path(&self) -> &Path;
+
+    /// The file as a [`DestPath`], if it's one
+    fn dest_path(&self) -> Option<&DestPath> {
+        None
+    }
+}
+
+macro_rules! as_path {
+    ($($type:ty),*) => {
+        $(
+            impl AsPath for $type {
+                fn as_path(&self) -> &Path {
+                    self.as_ref()
+                }
+            }
+        )*
     };
 }
 
-wrap!(fs, symlink_metadata, Metadata);
-wrap!(fs, read_link, PathBuf);
-wrap!(fs, read_dir, ReadDir);
-wrap!(fs, remove_dir_all, ());
-wrap!(fs, remove_file, ());
-wrap!(File, open, File);
-wrap2!(symlink, unix, ());
-wrap2!(copy, fs, u64);
+as_path!(Path, PathBuf, str, String, OsStr, OsString);
 
-macro_rules! make_error_message {
-    ($path:ident) => {
-        |err| Error::new(format!("{}: {}", $path.display(), err));
+impl AsPath for DestPath {
+    fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn dest_path(&self) -> Option<&DestPath> {
+        Some(self)
+    }
+}
+
+impl<T: AsPath + ?Sized> AsPath for &T {
+    fn as_path(&self) -> &Path {
+        (**self).as_path()
+    }
+
+    fn dest_path(&self) -> Option<&DestPath> {
+        (**self).dest_path()
+    }
+}
+
+/// A file passed as an [`AsPath`], which dereferences to its path.
+#[derive(Clone, Copy)]
+struct Reach<'a> {
+    path: &'a Path,
+    dest: Option<&'a DestPath>,
+}
+
+fn reach<P: AsPath + ?Sized>(path: &P) -> Reach<'_> {
+    Reach {
+        path: path.as_path(),
+        dest: path.dest_path(),
+    }
+}
+
+impl Deref for Reach<'_> {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.path
+    }
+}
+
+impl AsRef<Path> for Reach<'_> {
+    fn as_ref(&self) -> &Path {
+        self.path
+    }
+}
+
+impl AsPath for Reach<'_> {
+    fn as_path(&self) -> &Path {
+        self.path
+    }
+
+    fn dest_path(&self) -> Option<&DestPath> {
+        self.dest
+    }
+}
+
+/// A file to operate on relative to the directory containing it, by its name within that.
+struct At<'a> {
+    /// The directory containing the file, or `None` for the file to be reached by its path (as
+    /// `name`) as usual, as is any file which isn't beneath a [`SecureDirectory`]
+    directory: Option<Arc<File>>,
+    name: &'a Path,
+}
+
+impl At<'_> {
+    /// The directory to pass to an `*at` syscall, where `None` stands for the working directory.
+    fn fd(&self) -> Option<RawFd> {
+        self.directory
+            .as_ref()
+            .map(|directory| directory.as_raw_fd())
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.fd().unwrap_or(libc::AT_FDCWD)
+    }
+}
+
+// The directory through which to reach `path`: the one containing it if that's held open, or else
+// that walked down to from the root of the tree it's within (holding only one of the directories
+// along the way open at a time), if it's a `DestPath` within one
+fn at(path: Reach<'_>) -> io::Result<At<'_>> {
+    let by_path = At {
+        directory: None,
+        name: path.path,
+    };
+    let dest = match path.dest {
+        Some(dest) => dest,
+        None => return Ok(by_path),
+    };
+    let escapes = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path leads outside of the destination",
+        )
+    };
+    if let Some(directory) = &dest.directory {
+        let name = dest.path.file_name().ok_or_else(escapes)?;
+        return Ok(At {
+            directory: Some(Arc::clone(&directory.file)),
+            name: Path::new(name),
+        });
+    }
+    let root = match &dest.root {
+        Some(root) => root,
+        None => return Ok(by_path),
+    };
+    let mut components = dest
+        .path
+        .strip_prefix(&root.path)
+        .map_err(|_| escapes())?
+        .components();
+    let name = match components.next_back() {
+        Some(Component::Normal(name)) => name,
+        _ => return Err(escapes()),
     };
+    let mut directory = Arc::clone(&root.file);
+    for component in components {
+        let component = match component {
+            Component::Normal(component) => component,
+            _ => return Err(escapes()),
+        };
+        let fd = retry_on_eintr(|| {
+            fcntl::openat(directory.as_raw_fd(), component, WALK_FLAGS, Mode::empty())
+        })
+        .map_err(io_error)?;
+        directory = Arc::new(unsafe { File::from_raw_fd(fd) });
+    }
+    Ok(At {
+        directory: Some(directory),
+        name: Path::new(name),
+    })
 }
 
-pub fn create_dir<P: AsRef<Path>>(path: P, mode: u32) -> Result<(), Error> {
-    let path = path.as_ref();
-    DirBuilder::new()
-        .mode(mode)
-        .create(path)
+// The I/O error a `nix` error stands for, so that it's described as the standard library would
+fn io_error(err: nix::Error) -> io::Error {
+    err.as_errno()
+        .map_or_else(|| io::Error::other(err), io::Error::from)
+}
+
+// Open `path` with `flags` (along with `O_CLOEXEC`), creating it with `mode` if they include
+// `O_CREAT`
+fn open_at(path: Reach, flags: OFlag, mode: u32) -> io::Result<File> {
+    open_in(&at(path)?, flags, mode)
+}
+
+fn open_in(at: &At, flags: OFlag, mode: u32) -> io::Result<File> {
+    #[allow(clippy::useless_conversion)] // `mode_t` is not `u32` on every platform
+    let mode = Mode::from_bits_truncate(mode.try_into().map_err(io::Error::other)?);
+    let fd = retry_on_eintr(|| fcntl::openat(at.raw_fd(), at.name, flags | OFlag::O_CLOEXEC, mode))
+        .map_err(io_error)?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// Remove the directory `name` within `directory` and everything within it, reaching each of the
+// files within it through the directory containing it
+fn remove_dir_all_at(directory: RawFd, name: &Path) -> nix::Result<()> {
+    let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let mut entries = Dir::from_fd(retry_on_eintr(|| {
+        fcntl::openat(directory, name, flags, Mode::empty())
+    })?)?;
+    let fd = entries.as_raw_fd();
+    let names: Vec<CString> = entries
+        .iter()
+        .map(|entry| entry.map(|entry| entry.file_name().to_owned()))
+        .filter(|name| {
+            name.as_ref()
+                .map_or(true, |name| !matches!(name.as_bytes(), b"." | b".."))
+        })
+        .collect::<nix::Result<_>>()?;
+    for entry in names {
+        // Only directories can't be unlinked, which is cheaper than checking what each entry is
+        match unistd::unlinkat(Some(fd), entry.as_c_str(), UnlinkatFlags::NoRemoveDir) {
+            Err(nix::Error::Sys(Errno::EISDIR)) => {
+                remove_dir_all_at(fd, Path::new(OsStr::from_bytes(entry.as_bytes())))?
+            }
+            result => result?,
+        }
+    }
+    unistd::unlinkat(Some(directory), name, UnlinkatFlags::RemoveDir)
+}
+
+pub fn create_dir<P: AsPath>(path: P, mode: u32) -> Result<(), Error> {
+    let path = reach(&path);
+    #[allow(clippy::useless_conversion)] // `mode_t` is not `u32` on every platform
+    let mode = Mode::from_bits_truncate(mode.try_into()?);
+    at(path)
+        .and_then(|at| {
+            retry_on_eintr(|| stat::mkdirat(at.raw_fd(), at.name, mode)).map_err(io_error)
+        })
         .map_err(make_error_message!(path))
 }
 
-pub fn create<P: AsRef<Path>>(path: P, mode: u32) -> Result<File, Error> {
-    let path = path.as_ref();
-    OpenOptions::new()
-        .mode(mode)
-        .truncate(true)
-        .write(true)
-        .create(true)
-        .open(path)
+pub fn create<P: AsPath>(path: P, mode: u32) -> Result<File, Error> {
+    let path = reach(&path);
+    open_at(
+        path,
+        OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
+        mode,
+    )
+    .map_err(make_error_message!(path))
+}
+
+/// Open `path` for appending to, creating it if it doesn't exist.
+pub fn append<P: AsPath>(path: P) -> Result<File, Error> {
+    let path = reach(&path);
+    open_at(
+        path,
+        OFlag::O_WRONLY | OFlag::O_APPEND | OFlag::O_CREAT,
+        0o666,
+    )
+    .map_err(make_error_message!(path))
+}
+
+// An error which may be caused by a signal interrupting a system call before it did anything
+trait Interruptible {
+    fn is_interrupted(&self) -> bool;
+}
+
+impl Interruptible for io::Error {
+    fn is_interrupted(&self) -> bool {
+        self.kind() == io::ErrorKind::Interrupted
+    }
+}
+
+impl Interruptible for nix::Error {
+    fn is_interrupted(&self) -> bool {
+        self.as_errno() == Some(Errno::EINTR)
+    }
+}
+
+// Call `syscall` until it either succeeds or fails other than by being interrupted by a signal
+// (`EINTR`), which isn't a failure of the operation itself
+fn retry_on_eintr<T, E: Interruptible>(mut syscall: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    loop {
+        match syscall() {
+            Err(err) if err.is_interrupted() => {}
+            result => return result,
+        }
+    }
+}
+
+/// Copy up to `len` bytes from the current offset of `source` to the current offset of `dest`
+/// entirely within the kernel (allowing e.g. the filesystem to share the underlying extents),
+/// returning the number of bytes copied. A return value of zero signifies the end of `source`.
+#[cfg(target_os = "linux")]
+pub fn copy_file_range(source: &File, dest: &impl AsRawFd, len: usize) -> io::Result<usize> {
+    retry_on_eintr(|| fcntl::copy_file_range(source.as_raw_fd(), None, dest.as_raw_fd(), None, len))
+        .map_err(io_error)
+}
+
+/// Copy up to `len` bytes from the current offset of `source` to the current offset of `dest`
+/// within the kernel, like [`copy_file_range`], except that `sendfile` is supported between more
+/// kinds of files (including across filesystems) on older kernels.
+#[cfg(target_os = "linux")]
+pub fn sendfile(source: &File, dest: &impl AsRawFd, len: usize) -> io::Result<usize> {
+    retry_on_eintr(|| sendfile::sendfile(dest.as_raw_fd(), source.as_raw_fd(), None, len))
+        .map_err(io_error)
+}
+
+/// A regular file opened for reading along with its metadata, and the file created (with the same
+/// permissions) to copy its contents to.
+pub struct OpenFiles {
+    source: File,
+    dest: File,
+    metadata: Metadata,
+}
+
+// Open `source` for reading and create `dest` with `mode`, ready for copying the contents of the
+// former to the latter, unless they've already been `opened` (by a failed attempt to `clone`
+// them). If `preallocate` is set, space for the contents is allocated to `dest` up front.
+fn open_for_copy(
+    source: &Path,
+    dest: Reach,
+    mode: u32,
+    preallocate: bool,
+    opened: Option<OpenFiles>,
+) -> Result<(File, File, Metadata), Error> {
+    if let Some(opened) = opened {
+        if preallocate {
+            allocate(&opened.dest, opened.metadata.len()).map_err(make_error_message!(dest))?;
+        }
+        return Ok((opened.source, opened.dest, opened.metadata));
+    }
+    let source_file = open(source)?;
+    // Merely a hint (allowing the kernel to read further ahead), so failing to give it is harmless
+    #[cfg(target_os = "linux")]
+    let _ = fcntl::posix_fadvise(
+        source_file.as_raw_fd(),
+        0,
+        0,
+        PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL,
+    );
+    let metadata = source_file
+        .metadata()
+        .map_err(make_error_message!(source))?;
+    let dest_file = create(dest, mode)?;
+    // An existing file being copied over keeps its own mode otherwise
+    dest_file
+        .set_permissions(Permissions::from_mode(mode))
+        .map_err(make_error_message!(dest))?;
+    if preallocate {
+        allocate(&dest_file, metadata.len()).map_err(make_error_message!(dest))?;
+    }
+    Ok((source_file, dest_file, metadata))
+}
+
+// Allocate space for the first `len` bytes of `file` without changing its size, reducing its
+// fragmentation as it's written and failing early should there not be enough space. Where the
+// filesystem or platform doesn't support this, nothing is allocated.
+fn allocate(file: &File, len: u64) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let len = len.try_into().map_err(io::Error::other)?;
+        match retry_on_eintr(|| {
+            fcntl::fallocate(
+                file.as_raw_fd(),
+                FallocateFlags::FALLOC_FL_KEEP_SIZE,
+                0,
+                len,
+            )
+        }) {
+            Ok(()) | Err(nix::Error::Sys(Errno::EOPNOTSUPP | Errno::ENOSYS)) => Ok(()),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (file, len);
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+thread_local! {
+    // The buffer through which each thread copies contents in userspace. It's only ever grown, so
+    // that it's reused from one file to the next rather than being allocated for each.
+    static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// The size of the buffer through which to copy a file of length `len` in userspace: just large
+// enough for small files, growing with the file to at most a few megabytes.
+fn default_buffer_size(len: u64) -> usize {
+    const MIN: u64 = 1 << 12;
+    const MAX: u64 = 1 << 22;
+    cmp::min(cmp::max(len, MIN).next_power_of_two(), MAX) as usize
+}
+
+// Copy up to `len` bytes from the current offset of `source` to the current offset of `dest`,
+// stopping early upon reaching the end of `source`, and calling `progress` with the number of
+// bytes copied as each chunk is copied. If `in_kernel` is set, the copy is made within the kernel
+// where possible, first with `copy_file_range` and then with `sendfile`, and otherwise (or if it
+// isn't) in userspace, in chunks of up to `buffer_size` bytes. Either way, chunks are no longer
+// than `max_chunk` bytes, if given.
+#[cfg(target_os = "linux")]
+fn copy_contents(
+    source: &mut File,
+    dest: &mut (impl Write + AsRawFd),
+    len: u64,
+    buffer_size: usize,
+    max_chunk: Option<usize>,
+    in_kernel: bool,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<u64> {
+    let max_chunk = max_chunk.unwrap_or(usize::MAX);
+    let buffer_size = cmp::min(buffer_size, max_chunk);
+    let kernel_chunk = cmp::min(1 << 24, max_chunk);
+    let mut copied = 0;
+    // Where `dest` isn't a regular file (e.g. it's a pipe), `copy_file_range` fails with `EINVAL`,
+    // and where it's opened for appending (e.g. stdout redirected with `>>`), with `EBADF`
+    let unsupported = [
+        libc::EXDEV,
+        libc::EINVAL,
+        libc::ENOSYS,
+        libc::EOPNOTSUPP,
+        libc::EBADF,
+    ];
+    if in_kernel
+        && copy_in_kernel(
+            len,
+            kernel_chunk,
+            &mut copied,
+            progress,
+            &unsupported,
+            |chunk_len| copy_file_range(source, dest, chunk_len),
+        )?
+    {
+        return Ok(copied);
+    }
+    let unsupported = [libc::EINVAL, libc::ENOSYS, libc::EOPNOTSUPP];
+    if in_kernel
+        && copy_in_kernel(
+            len,
+            kernel_chunk,
+            &mut copied,
+            progress,
+            &unsupported,
+            |chunk_len| sendfile(source, dest, chunk_len),
+        )?
+    {
+        return Ok(copied);
+    }
+    // The file offsets have been advanced past whatever was already copied, so the userspace copy
+    // picks up right where the in-kernel copy left off.
+    BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        if buffer.len() < buffer_size {
+            buffer.resize(buffer_size, 0);
+        }
+        copy_through(
+            source,
+            dest,
+            len,
+            &mut buffer[..buffer_size],
+            copied,
+            progress,
+        )
+    })
+}
+
+// Continue copying with `copy_chunk`, which copies up to the given number of bytes (at most
+// `chunk_len`) within the kernel, until either `len` bytes have been copied in total (returning
+// `true`), or it fails with one of the `unsupported` errors (returning `false`, such that the copy
+// can be continued by other means). `copied` is kept up to date with the number of bytes copied.
+#[cfg(target_os = "linux")]
+fn copy_in_kernel(
+    len: u64,
+    chunk_len: usize,
+    copied: &mut u64,
+    progress: &mut impl FnMut(u64),
+    unsupported: &[i32],
+    mut copy_chunk: impl FnMut(usize) -> io::Result<usize>,
+) -> io::Result<bool> {
+    while *copied < len {
+        match copy_chunk(cmp::min(len - *copied, chunk_len as u64) as usize) {
+            Ok(0) if *copied > 0 => return Ok(true),
+            // Some pseudo-filesystems (e.g. procfs) report files as empty despite them having
+            // contents, which an in-kernel copy then faithfully copies nothing of.
+            Ok(0) => return Ok(false),
+            Ok(chunk_len) => {
+                *copied += chunk_len as u64;
+                progress(chunk_len as u64);
+            }
+            Err(err) => match err.raw_os_error() {
+                Some(errno) if unsupported.contains(&errno) => return Ok(false),
+                _ => return Err(err),
+            },
+        }
+    }
+    Ok(true)
+}
+
+// Continue copying in userspace through `buffer`, with `copied` bytes having already been copied.
+#[cfg(target_os = "linux")]
+fn copy_through(
+    source: &mut File,
+    dest: &mut impl Write,
+    len: u64,
+    buffer: &mut [u8],
+    mut copied: u64,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<u64> {
+    while copied < len {
+        let chunk_len = cmp::min(len - copied, buffer.len() as u64) as usize;
+        let chunk_len = match retry_on_eintr(|| source.read(&mut buffer[..chunk_len]))? {
+            0 => break,
+            chunk_len => chunk_len,
+        };
+        dest.write_all(&buffer[..chunk_len])?;
+        copied += chunk_len as u64;
+        progress(chunk_len as u64);
+    }
+    Ok(copied)
+}
+
+/// Copy the contents of the regular file `source` to `dest` (created with `mode`, or given it if it
+/// already exists), returning the number of bytes copied. As each chunk of the file is copied, `progress` is called with its length.
+/// Contents which have to be copied through userspace are copied `buffer_size` bytes at a time
+/// (by default, a size suited to the length of `source`), except on platforms where the copy is
+/// left entirely to the OS. If `preallocate` is set, space for the contents is allocated to `dest`
+/// before any are copied (where the filesystem supports it). Unless `in_kernel` is set, the
+/// contents are always read and written through userspace, never copied in a way which could
+/// share the underlying data (e.g. with `copy_file_range` on btrfs, or as a clone on macOS). The
+/// files may already have been `opened` by a failed attempt to [`clone`] them. If `max_chunk` is
+/// given, no more than that many bytes are copied at a time (whether in userspace or within the
+/// kernel), such that `progress` is called at least that often (e.g. to throttle the copy).
+#[allow(clippy::too_many_arguments)]
+pub fn copy<P: AsRef<Path>, Q: AsPath>(
+    source: P,
+    dest: Q,
+    mode: u32,
+    opened: Option<OpenFiles>,
+    buffer_size: Option<usize>,
+    max_chunk: Option<usize>,
+    preallocate: bool,
+    in_kernel: bool,
+    mut progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    #[cfg(target_os = "linux")]
+    {
+        let (mut source_file, mut dest_file, metadata) =
+            open_for_copy(source, dest, mode, preallocate, opened)?;
+        let buffer_size = buffer_size.unwrap_or_else(|| default_buffer_size(metadata.len()));
+        copy_contents(
+            &mut source_file,
+            &mut dest_file,
+            u64::MAX,
+            buffer_size,
+            max_chunk,
+            in_kernel,
+            &mut progress,
+        )
+        .map_err(make_error_message!(source, dest))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (buffer_size, max_chunk, preallocate);
+        // `fs::copy` clones files where it can, but gives the copy the mode of its source
+        let same_mode = || {
+            fs::metadata(source)
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o7777 == mode)
+        };
+        let copied = if in_kernel && opened.is_none() && same_mode() {
+            fs::copy(source, dest).map_err(make_error_message!(source, dest))?
+        } else {
+            let (mut source_file, mut dest_file, _) =
+                open_for_copy(source, dest, mode, false, opened)?;
+            io::copy(&mut source_file, &mut dest_file).map_err(make_error_message!(source, dest))?
+        };
+        progress(copied);
+        Ok(copied)
+    }
+}
+
+/// A read-only mapping of part of a file into memory, which is unmapped when dropped.
+struct Mapping {
+    address: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    /// Map the `len` bytes of `file` from `offset` (which must be a multiple of the page size).
+    fn new(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        let offset = offset.try_into().map_err(io::Error::other)?;
+        let address = unsafe {
+            mman::mmap(
+                ptr::null_mut(),
+                len,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_PRIVATE,
+                file.as_raw_fd(),
+                offset,
+            )
+        }
+        .map_err(io_error)?;
+        // The mapping is read through once from start to end, so the kernel may read ahead
+        // aggressively and drop each page once it's been read
+        let _ = unsafe { mman::madvise(address, len, MmapAdvise::MADV_SEQUENTIAL) };
+        Ok(Mapping { address, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.address.cast(), self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        let _ = unsafe { mman::munmap(self.address, self.len) };
+    }
+}
+
+/// Copy the contents of the regular file `source` to `dest` like [`copy`], except that `source` is
+/// read by mapping it into memory (at most 256 MiB of it at a time) rather than
+/// by reading from it, returning the number of bytes copied. If `source` can't be mapped at all,
+/// returns `Ok(None)`, in which case the caller should fall back to [`copy`]. Should `source` be
+/// truncated while it's being copied, the process is killed by `SIGBUS`.
+pub fn copy_mapped<P: AsRef<Path>, Q: AsPath>(
+    source: P,
+    dest: Q,
+    mode: u32,
+    opened: Option<OpenFiles>,
+    max_chunk: Option<usize>,
+    mut progress: impl FnMut(u64),
+) -> Result<Option<u64>, Error> {
+    const WINDOW: u64 = 1 << 28;
+    let chunk_len = max_chunk.map_or(1 << 24, |max_chunk| cmp::min(max_chunk, 1 << 24));
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    let (source_file, mut dest_file, metadata) = open_for_copy(source, dest, mode, false, opened)?;
+    let len = metadata.len();
+    let mut copied = 0;
+    while copied < len {
+        let window_len = cmp::min(len - copied, WINDOW) as usize;
+        let window = match Mapping::new(&source_file, copied, window_len) {
+            Ok(window) => window,
+            Err(_) if copied == 0 => return Ok(None),
+            Err(err) => return Err(make_error_message!(source)(err)),
+        };
+        // Written a chunk at a time, so that progress is reported (and throttled) as it's made
+        for chunk in window.as_slice().chunks(chunk_len) {
+            dest_file
+                .write_all(chunk)
+                .map_err(make_error_message!(source, dest))?;
+            progress(chunk.len() as u64);
+        }
+        copied += window_len as u64;
+    }
+    Ok(Some(copied))
+}
+
+/// Copy the contents of the regular file `source` to `dest` like [`copy`], except that both are
+/// opened with `O_DIRECT`, bypassing the page cache. Contents are copied through an
+/// aligned buffer of `buffer_size` bytes (rounded up to a multiple of the alignment `O_DIRECT`
+/// requires), except for any partial block at the end of `source`, which is written through the
+/// page cache. Where the filesystem doesn't support `O_DIRECT`, or on platforms other than Linux,
+/// this is equivalent to [`copy`].
+pub fn copy_direct<P: AsRef<Path>, Q: AsPath>(
+    source: P,
+    dest: Q,
+    mode: u32,
+    buffer_size: Option<usize>,
+    max_chunk: Option<usize>,
+    in_kernel: bool,
+    progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    #[cfg(target_os = "linux")]
+    {
+        // Enough for the logical block size of practically every device
+        const ALIGNMENT: usize = 1 << 12;
+        let mut progress = progress;
+        // Filesystems which don't support `O_DIRECT` (e.g. tmpfs) refuse to open files with it
+        let unsupported = |err: &io::Error| err.raw_os_error() == Some(libc::EINVAL);
+        let source_file = match OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(source)
+        {
+            Err(err) if unsupported(&err) => {
+                return copy(
+                    source,
+                    dest,
+                    mode,
+                    None,
+                    buffer_size,
+                    max_chunk,
+                    false,
+                    in_kernel,
+                    progress,
+                )
+            }
+            result => result.map_err(make_error_message!(source))?,
+        };
+        let metadata = source_file
+            .metadata()
+            .map_err(make_error_message!(source))?;
+        let dest_file = match open_at(
+            dest,
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC | OFlag::O_DIRECT,
+            mode,
+        ) {
+            Err(err) if unsupported(&err) => {
+                return copy(
+                    source,
+                    dest,
+                    mode,
+                    None,
+                    buffer_size,
+                    max_chunk,
+                    false,
+                    in_kernel,
+                    progress,
+                )
+            }
+            result => result.map_err(make_error_message!(dest))?,
+        };
+        dest_file
+            .set_permissions(Permissions::from_mode(mode))
+            .map_err(make_error_message!(dest))?;
+        let buffer_size = buffer_size.unwrap_or_else(|| default_buffer_size(metadata.len()));
+        let buffer_size =
+            max_chunk.map_or(buffer_size, |max_chunk| cmp::min(buffer_size, max_chunk));
+        let buffer_size = cmp::max(buffer_size.next_multiple_of(ALIGNMENT), ALIGNMENT);
+        // Over-allocated such that an aligned buffer of the full size lies somewhere within it
+        let mut allocation = vec![0; buffer_size + ALIGNMENT];
+        let start = allocation.as_ptr().align_offset(ALIGNMENT);
+        let buffer = &mut allocation[start..start + buffer_size];
+        let mut copied = 0;
+        loop {
+            let read = retry_on_eintr(|| source_file.read_at(buffer, copied))
+                .map_err(make_error_message!(source))?;
+            let aligned = read - read % ALIGNMENT;
+            dest_file
+                .write_all_at(&buffer[..aligned], copied)
+                .map_err(make_error_message!(source, dest))?;
+            if aligned < read {
+                // The partial block at the end of the file can't be written directly
+                open_at(dest, OFlag::O_WRONLY, 0)
+                    .and_then(|tail| {
+                        tail.write_all_at(&buffer[aligned..read], copied + aligned as u64)
+                    })
+                    .map_err(make_error_message!(source, dest))?;
+            }
+            copied += read as u64;
+            progress(read as u64);
+            // Any read short of a whole number of blocks can only have reached the end of the file
+            if read == 0 || aligned < read {
+                return Ok(copied);
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        copy(
+            source,
+            dest,
+            mode,
+            None,
+            buffer_size,
+            max_chunk,
+            false,
+            in_kernel,
+            progress,
+        )
+    }
+}
+
+/// Write the contents of the regular file `source` to stdout, like [`copy`], returning the number
+/// of bytes written.
+pub fn copy_to_stdout<P: AsRef<Path>>(source: P, buffer_size: Option<usize>) -> Result<u64, Error> {
+    let source = source.as_ref();
+    let mut source_file = open(source)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    #[cfg(target_os = "linux")]
+    let copied = {
+        let len = source_file
+            .metadata()
+            .map_err(make_error_message!(source))?
+            .len();
+        let buffer_size = buffer_size.unwrap_or_else(|| default_buffer_size(len));
+        copy_contents(
+            &mut source_file,
+            &mut stdout,
+            u64::MAX,
+            buffer_size,
+            None,
+            true,
+            &mut |_| {},
+        )
+    };
+    #[cfg(not(target_os = "linux"))]
+    let copied = {
+        let _ = buffer_size;
+        io::copy(&mut source_file, &mut stdout)
+    };
+    let copied = copied.map_err(make_error_message!(source))?;
+    stdout.flush().map_err(make_error_message!(source))?;
+    Ok(copied)
+}
+
+/// Copy the contents of the block device `source` to `dest` (a regular file), returning the number
+/// of bytes copied, and calling `progress` with the length of each chunk as it's copied. Where the
+/// size of the device can be determined, exactly that many bytes are copied, failing should fewer
+/// be read; otherwise it's read until its end.
+pub fn copy_device<P: AsRef<Path>>(
+    source: P,
+    dest: &mut File,
+    buffer_size: Option<usize>,
+    mut progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    let source = source.as_ref();
+    let mut source_file = open(source)?;
+    #[cfg(target_os = "linux")]
+    let (size, copied) = {
+        let mut size = 0;
+        let size = unsafe { ioctl::blkgetsize64(source_file.as_raw_fd(), &mut size) }
+            .ok()
+            .map(|_| size);
+        let len = size.unwrap_or(u64::MAX);
+        let buffer_size = buffer_size.unwrap_or_else(|| default_buffer_size(len));
+        let copied = copy_contents(
+            &mut source_file,
+            dest,
+            len,
+            buffer_size,
+            None,
+            true,
+            &mut progress,
+        );
+        (size, copied)
+    };
+    #[cfg(not(target_os = "linux"))]
+    let (size, copied) = {
+        let _ = buffer_size;
+        let copied = io::copy(&mut source_file, dest);
+        if let Ok(copied) = copied {
+            progress(copied);
+        }
+        (None::<u64>, copied)
+    };
+    let copied = copied.map_err(make_error_message!(source))?;
+    match size {
+        Some(size) if copied < size => Err(Error::new(format!(
+            "{}: read only {} of the {} bytes of the device",
+            source.display(),
+            copied,
+            size
+        ))),
+        _ => Ok(copied),
+    }
+}
+
+/// Resume copying the regular file `source` to `dest` like [`copy`], where the first `offset`
+/// bytes of `source` have already been copied to `dest`, returning the number of bytes copied in
+/// addition to those. The contents of `dest` up to `offset` are left as they are.
+pub fn copy_from<P: AsRef<Path>, Q: AsPath>(
+    source: P,
+    dest: Q,
+    offset: u64,
+    buffer_size: Option<usize>,
+    max_chunk: Option<usize>,
+    in_kernel: bool,
+    mut progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    let mut source_file = open(source)?;
+    let mut dest_file = open_at(dest, OFlag::O_WRONLY, 0).map_err(make_error_message!(dest))?;
+    source_file
+        .seek(SeekFrom::Start(offset))
+        .map_err(make_error_message!(source))?;
+    dest_file
+        .seek(SeekFrom::Start(offset))
+        .map_err(make_error_message!(dest))?;
+    #[cfg(target_os = "linux")]
+    {
+        let len = source_file
+            .metadata()
+            .map_err(make_error_message!(source))?
+            .len();
+        let buffer_size =
+            buffer_size.unwrap_or_else(|| default_buffer_size(len.saturating_sub(offset)));
+        copy_contents(
+            &mut source_file,
+            &mut dest_file,
+            u64::MAX,
+            buffer_size,
+            max_chunk,
+            in_kernel,
+            &mut progress,
+        )
+        .map_err(make_error_message!(source, dest))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (buffer_size, max_chunk, in_kernel);
+        let copied = io::copy(&mut source_file, &mut dest_file)
+            .map_err(make_error_message!(source, dest))?;
+        progress(copied);
+        Ok(copied)
+    }
+}
+
+/// Copy the regular file `source` to `dest` like [`copy`], except that only the regions of
+/// `source` which contain data are copied, thereby preserving any holes in `dest`. On platforms
+/// where holes can't be found this is equivalent to [`copy`].
+#[allow(clippy::too_many_arguments)]
+pub fn copy_sparse<P: AsRef<Path>, Q: AsPath>(
+    source: P,
+    dest: Q,
+    mode: u32,
+    opened: Option<OpenFiles>,
+    buffer_size: Option<usize>,
+    max_chunk: Option<usize>,
+    in_kernel: bool,
+    progress: impl FnMut(u64),
+) -> Result<u64, Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let (source, dest) = (source.as_ref(), reach(&dest));
+        let mut progress = progress;
+        let (mut source_file, mut dest_file, metadata) =
+            open_for_copy(source, dest, mode, false, opened)?;
+        let buffer_size = buffer_size.unwrap_or_else(|| default_buffer_size(metadata.len()));
+        let fd = source_file.as_raw_fd();
+        let (mut offset, mut copied) = (0, 0);
+        while offset < metadata.len() {
+            let data_start = match unistd::lseek(fd, offset as i64, Whence::SeekData) {
+                Ok(data_start) => data_start as u64,
+                // There's no more data, so the remainder of the file is a hole
+                Err(nix::Error::Sys(Errno::ENXIO)) => break,
+                Err(err) => return Err(make_error_message!(source)(err)),
+            };
+            let data_end = unistd::lseek(fd, data_start as i64, Whence::SeekHole)
+                .map_err(make_error_message!(source))? as u64;
+            source_file
+                .seek(SeekFrom::Start(data_start))
+                .map_err(make_error_message!(source))?;
+            dest_file
+                .seek(SeekFrom::Start(data_start))
+                .map_err(make_error_message!(dest))?;
+            copied += copy_contents(
+                &mut source_file,
+                &mut dest_file,
+                data_end - data_start,
+                buffer_size,
+                max_chunk,
+                in_kernel,
+                &mut progress,
+            )
+            .map_err(make_error_message!(source, dest))?;
+            offset = data_end;
+        }
+        // Extending the file to its full length leaves any trailing hole unallocated.
+        dest_file
+            .set_len(metadata.len())
+            .map_err(make_error_message!(dest))?;
+        Ok(copied)
+    }
+    #[cfg(not(target_os = "linux"))]
+    copy(
+        source,
+        dest,
+        mode,
+        opened,
+        buffer_size,
+        max_chunk,
+        false,
+        in_kernel,
+        progress,
+    )
+}
+
+/// Copy the regular file `source` to `dest` like [`copy`], except that the contents are split into
+/// `chunks` regions of (roughly) equal length, each of which is copied concurrently on a thread of
+/// its own at its offset within `dest`. If `sparse` is set, blocks of `source` consisting entirely
+/// of zeros are left as holes in `dest` rather than being written, and otherwise space is allocated
+/// to `dest` up front if `preallocate` is. Unlike [`copy`], `progress` is also called with the
+/// length of the regions skipped over as holes.
+#[allow(clippy::too_many_arguments)]
+pub fn copy_parallel<P: AsRef<Path>, Q: AsPath>(
+    source: P,
+    dest: Q,
+    mode: u32,
+    opened: Option<OpenFiles>,
+    chunks: usize,
+    buffer_size: Option<usize>,
+    max_chunk: Option<usize>,
+    sparse: bool,
+    preallocate: bool,
+    progress: impl Fn(u64) + Sync,
+) -> Result<u64, Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    let (source_file, dest_file, metadata) =
+        open_for_copy(source, dest, mode, preallocate && !sparse, opened)?;
+    let len = metadata.len();
+    // Any regions which are never written remain holes
+    dest_file.set_len(len).map_err(make_error_message!(dest))?;
+    // Chunks are aligned to block boundaries, such that only the final chunk may be shorter
+    let chunk_len = cmp::max(len / chunks as u64, 1).next_multiple_of(1 << 12);
+    let buffer_size = buffer_size.unwrap_or_else(|| default_buffer_size(chunk_len));
+    let buffer_size = max_chunk.map_or(buffer_size, |max_chunk| cmp::min(buffer_size, max_chunk));
+    let (source_file, dest_file, progress) = (&source_file, &dest_file, &progress);
+    thread::scope(|scope| {
+        let threads: Vec<_> = (0..len)
+            .step_by(chunk_len as usize)
+            .map(|start| {
+                let end = cmp::min(start + chunk_len, len);
+                scope.spawn(move || {
+                    let mut buffer = vec![0; buffer_size];
+                    copy_region(
+                        source_file,
+                        dest_file,
+                        start..end,
+                        &mut buffer,
+                        sparse,
+                        progress,
+                    )
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .try_fold(0, |copied, thread| {
+                thread
+                    .join()
+                    .unwrap()
+                    .map(|chunk_copied| copied + chunk_copied)
+            })
+            .map_err(make_error_message!(source, dest))
+    })
+}
+
+// Copy the bytes within `region` of `source` to the same offsets within `dest` through
+// `buffer`, with blocks of zeros left unwritten if `sparse` is set. The region ends early if
+// the end of `source` is reached.
+fn copy_region(
+    source: &File,
+    dest: &File,
+    region: Range<u64>,
+    buffer: &mut [u8],
+    sparse: bool,
+    progress: &impl Fn(u64),
+) -> io::Result<u64> {
+    let mut offset = region.start;
+    while offset < region.end {
+        let chunk_len = cmp::min(region.end - offset, buffer.len() as u64) as usize;
+        let chunk_len = match retry_on_eintr(|| source.read_at(&mut buffer[..chunk_len], offset))? {
+            0 => break,
+            chunk_len => chunk_len,
+        };
+        let chunk = &buffer[..chunk_len];
+        if !sparse || chunk.iter().any(|&byte| byte != 0) {
+            dest.write_all_at(chunk, offset)?;
+        }
+        offset += chunk_len as u64;
+        progress(chunk_len as u64);
+    }
+    Ok(offset - region.start)
+}
+
+// Hash the contents of the file at `path`, up to its first `len` bytes
+fn checksum(path: Reach, len: u64) -> Result<u64, Error> {
+    let mut file = open(path)?.take(len);
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0; 1 << 17];
+    loop {
+        match retry_on_eintr(|| file.read(&mut buffer)).map_err(make_error_message!(path))? {
+            0 => return Ok(hasher.digest()),
+            len => hasher.update(&buffer[..len]),
+        }
+    }
+}
+
+/// Check that the contents of the regular file `dest` are identical to those of `source`, which
+/// it's a copy of, by comparing their hashes. As copies are often made entirely within the kernel,
+/// both files are read in full to do so.
+pub fn verify<P: AsRef<Path>, Q: AsPath>(source: P, dest: Q) -> Result<(), Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    if checksum(reach(source), u64::MAX)? == checksum(dest, u64::MAX)? {
+        return Ok(());
+    }
+    Err(Error {
+        kind: ErrorKind::ChecksumMismatch,
+        path: Some(source.to_path_buf()),
+        message: format!(
+            "{}, {}: contents of copy differ from source",
+            source.display(),
+            dest.display()
+        ),
+        source: None,
+    })
+}
+
+/// Whether the contents of the regular file `dest`, which is `len` bytes long, are identical to the
+/// first `len` bytes of `source` (i.e. it's a correct partial copy of it), by comparing their
+/// hashes.
+pub fn verify_prefix<P: AsPath, Q: AsPath>(source: P, dest: Q, len: u64) -> Result<bool, Error> {
+    Ok(checksum(reach(&source), len)? == checksum(reach(&dest), len)?)
+}
+
+/// Whether the regular files `source` and `dest` have the same contents, comparing their sizes
+/// before reading both (and stopping at the first difference between them).
+pub fn identical<P: AsRef<Path>, Q: AsPath>(source: P, dest: Q) -> Result<bool, Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    let (mut source_file, mut dest_file) = (open(source)?, open(dest)?);
+    let metadata = |file: &File, path: &Path| file.metadata().map_err(make_error_message!(path));
+    if metadata(&source_file, source)?.len() != metadata(&dest_file, &dest)?.len() {
+        return Ok(false);
+    }
+    let (mut source_buffer, mut dest_buffer) = (vec![0; 1 << 17], vec![0; 1 << 17]);
+    loop {
+        let read =
+            fill(&mut source_file, &mut source_buffer).map_err(make_error_message!(source))?;
+        if fill(&mut dest_file, &mut dest_buffer).map_err(make_error_message!(dest))? != read
+            || source_buffer[..read] != dest_buffer[..read]
+        {
+            return Ok(false);
+        }
+        if read < source_buffer.len() {
+            return Ok(true);
+        }
+    }
+}
+
+// Read from `file` until `buffer` is full or the end of the file is reached, returning the number
+// of bytes read
+fn fill(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match retry_on_eintr(|| file.read(&mut buffer[filled..]))? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Whether `metadata` describes a file with fewer blocks allocated to it than are needed to
+/// store its contents, suggesting that it contains holes.
+pub fn is_sparse(metadata: &Metadata) -> bool {
+    metadata.blocks() * 512 < metadata.len()
+}
+
+#[cfg(target_os = "linux")]
+mod ioctl {
+    // FICLONE and BLKGETSIZE64, from linux/fs.h
+    nix::ioctl_write_int!(ficlone, 0x94, 9);
+    nix::ioctl_read!(blkgetsize64, 0x12, 114, u64);
+}
+
+/// The outcome of attempting to [`clone`] a file.
+pub enum Cloned {
+    /// The destination was created as a clone
+    Cloned,
+    /// Cloning isn't possible, leaving the files opened in attempting it (if any) for the copy
+    /// falling back from it to copy between, rather than opening them again
+    Unsupported(Option<OpenFiles>),
+}
+
+/// Attempt to create `dest` as a copy-on-write clone of the regular file `source`, sharing its
+/// underlying data (holes included) until either is modified. Returns [`Cloned::Unsupported`] if
+/// cloning isn't possible (e.g. the platform or filesystem doesn't support it, `dest` already
+/// exists on macOS, or `source` and `dest` reside on different volumes), in which case the caller
+/// should fall back to a normal copy. On Linux, `dest` is created (or truncated) with `mode`
+/// either way, and is left open along with `source` to be copied to by the fallback. On macOS,
+/// where clones are created with the mode of their source, they're only created if that's `mode`.
+pub fn clone<P: AsRef<Path>, Q: AsPath>(source: P, dest: Q, mode: u32) -> Result<Cloned, Error> {
+    #[cfg(target_os = "macos")]
+    {
+        const CLONE_NOFOLLOW: u32 = 0x0001;
+        let (source, dest) = (source.as_ref(), reach(&dest));
+        if symlink_metadata(source)?.permissions().mode() & 0o7777 != mode {
+            return Ok(Cloned::Unsupported(None));
+        }
+        let (source_c, dest_c) = (c_path(source)?, c_path(&dest)?);
+        if unsafe { libc::clonefile(source_c.as_ptr(), dest_c.as_ptr(), CLONE_NOFOLLOW) } == 0 {
+            return Ok(Cloned::Cloned);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENOTSUP) | Some(libc::EXDEV) | Some(libc::EEXIST) | Some(libc::ENOSYS) => {
+                Ok(Cloned::Unsupported(None))
+            }
+            _ => Err(make_error_message!(source, dest)(err)),
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let (source, dest) = (source.as_ref(), reach(&dest));
+        let (source_file, dest_file, metadata) = open_for_copy(source, dest, mode, false, None)?;
+        // The descriptor is passed as the argument itself, rather than a pointer to it
+        let source_fd = source_file.as_raw_fd() as libc::c_ulong;
+        match retry_on_eintr(|| unsafe { ioctl::ficlone(dest_file.as_raw_fd(), source_fd) }) {
+            Ok(_) => Ok(Cloned::Cloned),
+            Err(err) => match err.as_errno() {
+                Some(Errno::EXDEV) | Some(Errno::EOPNOTSUPP) | Some(Errno::EINVAL) => {
+                    Ok(Cloned::Unsupported(Some(OpenFiles {
+                        source: source_file,
+                        dest: dest_file,
+                        metadata,
+                    })))
+                }
+                _ => Err(make_error_message!(source, dest)(err)),
+            },
+        }
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        let _ = (source, dest, mode);
+        Ok(Cloned::Unsupported(None))
+    }
+}
+
+pub fn mkfifo<P: AsPath>(path: P, permissions: Permissions) -> Result<(), Error> {
+    let path = reach(&path);
+    #[allow(clippy::useless_conversion)] // `mode_t` is not `u32` on every platform
+    let mode = Mode::from_bits_truncate(permissions.mode().try_into()?);
+    let at = at(path).map_err(make_error_message!(path))?;
+    #[cfg(target_os = "linux")]
+    let result = retry_on_eintr(|| unistd::mkfifoat(at.fd(), at.name, mode));
+    // Where there's no `mkfifoat`, nothing is reached through its directory
+    #[cfg(not(target_os = "linux"))]
+    let result = retry_on_eintr(|| unistd::mkfifo(at.name, mode));
+    result.map_err(make_error_message!(path))
+}
+
+/// Create a device node at `path` of the same type, mode, and device number as those recorded in
+/// `metadata` (which must describe a character or block device).
+pub fn mknod<P: AsPath>(path: P, metadata: &Metadata) -> Result<(), Error> {
+    let path = reach(&path);
+    let kind = if metadata.file_type().is_char_device() {
+        SFlag::S_IFCHR
+    } else {
+        SFlag::S_IFBLK
+    };
+    #[allow(clippy::useless_conversion)] // `mode_t` and `dev_t` are not the same on every platform
+    let (mode, device) = (
+        Mode::from_bits_truncate(metadata.permissions().mode().try_into()?),
+        metadata.rdev().try_into()?,
+    );
+    let at = at(path).map_err(make_error_message!(path))?;
+    #[cfg(target_os = "linux")]
+    let result = {
+        let name = c_path(at.name)?;
+        retry_on_eintr(|| {
+            let mode = kind.bits() | mode.bits();
+            Errno::result(unsafe { libc::mknodat(at.raw_fd(), name.as_ptr(), mode, device) })
+                .map(drop)
+        })
+    };
+    // Where there's no `mknodat`, nothing is reached through its directory
+    #[cfg(not(target_os = "linux"))]
+    let result = retry_on_eintr(|| stat::mknod(at.name, kind, mode, device));
+    result.map_err(|err| {
+        let message = format!("{}: cannot create device node: {}", path.display(), err);
+        Error::with_cause(err, &path, message)
+    })
+}
+
+/// Set the mode of `path` (following it if it is a symlink) to `permissions`, including the
+/// setuid, setgid, and sticky bits.
+pub fn set_permissions<P: AsPath>(path: P, permissions: Permissions) -> Result<(), Error> {
+    let path = reach(&path);
+    #[allow(clippy::useless_conversion)] // `mode_t` is not `u32` on every platform
+    let mode = Mode::from_bits_truncate(permissions.mode().try_into()?);
+    at(path)
+        .and_then(|at| {
+            stat::fchmodat(at.fd(), at.name, mode, FchmodatFlags::FollowSymlink).map_err(io_error)
+        })
         .map_err(make_error_message!(path))
 }
 
-pub fn mkfifo<P: AsRef<Path>>(path: P, permissions: Permissions) -> Result<(), Error> {
+/// The process's file mode creation mask.
+pub fn umask() -> u32 {
+    // The mask can only be read by replacing it, so the original is restored straight away
+    let mask = stat::umask(Mode::empty());
+    stat::umask(mask);
+    #[allow(clippy::useless_conversion)] // `mode_t` is not `u32` on every platform
+    mask.bits().into()
+}
+
+/// Set the access and modification times (and, where possible, the creation time) of `path` to
+/// those recorded in `metadata`. If `path` is a symlink, the times of the link itself are set
+/// rather than those of its target.
+pub fn set_timestamps<P: AsPath>(path: P, metadata: &Metadata) -> Result<(), Error> {
+    let path = reach(&path);
+    let atime = TimeSpec::from(timespec {
+        tv_sec: metadata.atime(),
+        tv_nsec: metadata.atime_nsec(),
+    });
+    let mtime = TimeSpec::from(timespec {
+        tv_sec: metadata.mtime(),
+        tv_nsec: metadata.mtime_nsec(),
+    });
+    let at = at(path).map_err(make_error_message!(path))?;
+    stat::utimensat(
+        at.fd(),
+        at.name,
+        &atime,
+        &mtime,
+        UtimensatFlags::NoFollowSymlink,
+    )
+    .map_err(make_error_message!(path))?;
+    set_birthtime(&path, metadata)
+}
+
+/// Set the creation time of `path` (or of the link itself, if it is a symlink) to that recorded
+/// in `metadata`, on platforms where it can be set. Elsewhere, or if the filesystem doesn't
+/// record creation times, it's left as it is.
+fn set_birthtime(path: &Path, metadata: &Metadata) -> Result<(), Error> {
+    #[cfg(target_os = "macos")]
+    {
+        use nix::libc::{c_char, c_int, c_uint, c_void, size_t};
+        use std::os::macos::fs::MetadataExt;
+
+        #[repr(C)]
+        struct AttrList {
+            bitmapcount: u16,
+            reserved: u16,
+            commonattr: u32,
+            volattr: u32,
+            dirattr: u32,
+            fileattr: u32,
+            forkattr: u32,
+        }
+        const ATTR_BIT_MAP_COUNT: u16 = 5;
+        const ATTR_CMN_CRTIME: u32 = 0x0000_0200;
+        const FSOPT_NOFOLLOW: c_uint = 0x0000_0001;
+        extern "C" {
+            fn setattrlist(
+                path: *const c_char,
+                attr_list: *mut AttrList,
+                attr_buf: *mut c_void,
+                attr_buf_size: size_t,
+                options: c_uint,
+            ) -> c_int;
+        }
+
+        let path_c = c_path(path)?;
+        let mut attributes = AttrList {
+            bitmapcount: ATTR_BIT_MAP_COUNT,
+            reserved: 0,
+            commonattr: ATTR_CMN_CRTIME,
+            volattr: 0,
+            dirattr: 0,
+            fileattr: 0,
+            forkattr: 0,
+        };
+        let mut birthtime = timespec {
+            tv_sec: metadata.st_birthtime(),
+            tv_nsec: metadata.st_birthtime_nsec(),
+        };
+        let result = unsafe {
+            setattrlist(
+                path_c.as_ptr(),
+                &mut attributes,
+                (&mut birthtime as *mut timespec).cast(),
+                std::mem::size_of::<timespec>(),
+                FSOPT_NOFOLLOW,
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENOTSUP) | Some(libc::EINVAL) => Ok(()),
+            _ => Err(make_error_message!(path)(err)),
+        }
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = (path, metadata);
+        Ok(())
+    }
+}
+
+/// Restore the access time of `path` (following it if it is a symlink) to that recorded in
+/// `metadata`, leaving its modification time as it is. This undoes the update to the access time
+/// made by reading the file.
+pub fn restore_atime<P: AsRef<Path>>(path: P, metadata: &Metadata) -> Result<(), Error> {
     let path = path.as_ref();
-    let mode = Mode::from_bits_truncate(permissions.mode().try_into()?);
-    unistd::mkfifo(path, mode).map_err(make_error_message!(path))
+    let atime = TimeSpec::from(timespec {
+        tv_sec: metadata.atime(),
+        tv_nsec: metadata.atime_nsec(),
+    });
+    let mtime = TimeSpec::from(timespec {
+        tv_sec: 0,
+        tv_nsec: libc::UTIME_OMIT,
+    });
+    stat::utimensat(None, path, &atime, &mtime, UtimensatFlags::FollowSymlink)
+        .map_err(make_error_message!(path))
+}
+
+/// Set the owner and group of `path` to those recorded in `metadata`. If `path` is a symlink, the
+/// ownership of the link itself is changed rather than that of its target.
+pub fn set_ownership<P: AsPath>(path: P, metadata: &Metadata) -> Result<(), Error> {
+    let path = reach(&path);
+    let (owner, group) = (Uid::from_raw(metadata.uid()), Gid::from_raw(metadata.gid()));
+    let at = at(path).map_err(make_error_message!(path))?;
+    retry_on_eintr(|| {
+        unistd::fchownat(
+            at.fd(),
+            at.name,
+            Some(owner),
+            Some(group),
+            FchownatFlags::NoFollowSymlink,
+        )
+    })
+    .map_err(make_error_message!(path))
+}
+
+mod xattr {
+    use nix::libc::{self, c_char, c_void, size_t, ssize_t};
+    use std::ffi::{CStr, CString};
+    #[cfg(target_os = "linux")]
+    use std::fs::File;
+    use std::io;
+    #[cfg(target_os = "linux")]
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    /// A file to set extended attributes on: either by its path (without following it, if it's a
+    /// symlink), or through the file itself, opened as `File`.
+    pub enum Target {
+        Path(CString),
+        #[cfg(target_os = "linux")]
+        File(File),
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn list(path: &CStr, buffer: *mut c_char, size: size_t) -> ssize_t {
+        libc::llistxattr(path.as_ptr(), buffer, size)
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn list(path: &CStr, buffer: *mut c_char, size: size_t) -> ssize_t {
+        libc::listxattr(path.as_ptr(), buffer, size, libc::XATTR_NOFOLLOW)
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn get(path: &CStr, name: &CStr, buffer: *mut c_void, size: size_t) -> ssize_t {
+        libc::lgetxattr(path.as_ptr(), name.as_ptr(), buffer, size)
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn get(path: &CStr, name: &CStr, buffer: *mut c_void, size: size_t) -> ssize_t {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buffer,
+            size,
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    }
+
+    // Calls `read` once to determine the size of the buffer needed, and then again to fill it,
+    // retrying should the value have grown in the interim.
+    fn read_to_vec(mut read: impl FnMut(*mut c_void, size_t) -> ssize_t) -> io::Result<Vec<u8>> {
+        loop {
+            let size = read(ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0; size as usize];
+            let size = read(buffer.as_mut_ptr().cast(), buffer.len());
+            if size >= 0 {
+                buffer.truncate(size as usize);
+                return Ok(buffer);
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ERANGE) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// The NUL-terminated names of each extended attribute of `path`, concatenated together.
+    pub fn list_names(path: &CStr) -> io::Result<Vec<u8>> {
+        read_to_vec(|buffer, size| unsafe { list(path, buffer.cast(), size) })
+    }
+
+    pub fn get_value(path: &CStr, name: &CStr) -> io::Result<Vec<u8>> {
+        read_to_vec(|buffer, size| unsafe { get(path, name, buffer, size) })
+    }
+
+    pub fn set_value(target: &Target, name: &CStr, value: &[u8]) -> io::Result<()> {
+        let value_ptr = value.as_ptr().cast();
+        #[cfg(target_os = "linux")]
+        let result = match target {
+            Target::Path(path) => unsafe {
+                libc::lsetxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0)
+            },
+            Target::File(file) => unsafe {
+                libc::fsetxattr(file.as_raw_fd(), name.as_ptr(), value_ptr, value.len(), 0)
+            },
+        };
+        #[cfg(target_os = "macos")]
+        let result = {
+            let Target::Path(path) = target;
+            unsafe {
+                libc::setxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value_ptr,
+                    value.len(),
+                    0,
+                    libc::XATTR_NOFOLLOW,
+                )
+            }
+        };
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn c_path(path: &Path) -> Result<CString, Error> {
+    CString::new(path.as_os_str().as_bytes()).map_err(make_error_message!(path))
+}
+
+// The file to set the extended attributes of `path` on. They can't be set relative to a directory,
+// so a file beneath a `SecureDirectory` is opened through its directory (without following it) to
+// set them on. Only regular files and directories are opened to do so, as opening a device may
+// well act on it, and so there's nothing to set them on for any other file there.
+fn xattr_target(path: Reach) -> Result<Option<xattr::Target>, Error> {
+    let at = at(path).map_err(make_error_message!(path))?;
+    #[cfg(target_os = "linux")]
+    if at.fd().is_some() {
+        let file_type = open_in(&at, OFlag::O_PATH | OFlag::O_NOFOLLOW, 0)
+            .and_then(|file| file.metadata())
+            .map_err(make_error_message!(path))?
+            .file_type();
+        if !file_type.is_file() && !file_type.is_dir() {
+            return Ok(None);
+        }
+        // Files which can't be read (such as those with modes of 0o200) can still be written
+        let flags = OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK | OFlag::O_NOCTTY;
+        return open_in(&at, OFlag::O_RDONLY | flags, 0)
+            .or_else(|_| open_in(&at, OFlag::O_WRONLY | flags, 0))
+            .map(|file| Some(xattr::Target::File(file)))
+            .map_err(make_error_message!(path));
+    }
+    drop(at);
+    c_path(&path).map(|path| Some(xattr::Target::Path(path)))
+}
+
+// The error for being unable to set extended attributes (or ACLs, if `acl` is set) on `path` as
+// there's nothing to set them on (see `xattr_target`)
+fn no_xattr_target(path: &Path, acl: bool) -> Error {
+    let message = format!(
+        "{}: cannot set {} of a symlink or special file through its directory",
+        path.display(),
+        if acl { "ACLs" } else { "extended attributes" }
+    );
+    Error::with_cause(
+        io::Error::from_raw_os_error(libc::EOPNOTSUPP),
+        path,
+        message,
+    )
+}
+
+// Whether failing to set an extended attribute with `err` is because this particular attribute
+// can't be set on the destination, rather than because of a problem with the destination itself
+fn is_unsettable(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(code) if code == libc::EPERM || code == libc::ENOTSUP || code == libc::EOPNOTSUPP
+    )
+}
+
+/// Copy each extended attribute of `source` onto `dest` (without following symlinks). Attributes
+/// which the process lacks the privilege to set (such as those in the `security` namespace for an
+/// unprivileged user) or which the destination's filesystem doesn't support are skipped, with the
+/// errors for them being returned.
+pub fn copy_xattrs<P: AsRef<Path>, Q: AsPath>(source: P, dest: Q) -> Result<Vec<Error>, Error> {
+    let (source, dest) = (source.as_ref(), reach(&dest));
+    let source_c = c_path(source)?;
+    let names = match xattr::list_names(&source_c) {
+        Ok(names) => names,
+        Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(err) => return Err(make_error_message!(source)(err)),
+    };
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let target = match xattr_target(dest)? {
+        Some(target) => target,
+        None => return Ok(vec![no_xattr_target(&dest, false)]),
+    };
+    let mut skipped = Vec::new();
+    for name in names.split_inclusive(|&byte| byte == 0) {
+        let name = CStr::from_bytes_with_nul(name).map_err(make_error_message!(source))?;
+        let attribute_error = |err: io::Error| {
+            let message = format!("{}: {}: {}", dest.display(), name.to_string_lossy(), err);
+            Error::with_cause(err, &dest, message)
+        };
+        let value = xattr::get_value(&source_c, name).map_err(make_error_message!(source))?;
+        match xattr::set_value(&target, name, &value) {
+            Ok(()) => {}
+            Err(err) if is_unsettable(&err) => skipped.push(attribute_error(err)),
+            Err(err) => return Err(attribute_error(err)),
+        }
+    }
+    Ok(skipped)
+}
+
+/// Copy the POSIX access ACL of `source` (as well as its default ACL, if it is a directory) onto
+/// `dest`. As applying an access ACL rewrites the group permission bits of `dest`'s mode, this must
+/// be done after its mode has been set. ACLs are only supported on Linux, and so on other platforms
+/// this does nothing.
+pub fn copy_acls<P: AsRef<Path>, Q: AsPath>(source: P, dest: Q) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let (source, dest) = (source.as_ref(), reach(&dest));
+        let source_c = c_path(source)?;
+        let mut target = None;
+        for name in &["system.posix_acl_access\0", "system.posix_acl_default\0"] {
+            let name = CStr::from_bytes_with_nul(name.as_bytes()).unwrap();
+            let value = match xattr::get_value(&source_c, name) {
+                Ok(value) => value,
+                Err(err) => match err.raw_os_error() {
+                    Some(libc::ENODATA) | Some(libc::ENOTSUP) => continue,
+                    _ => return Err(make_error_message!(source)(err)),
+                },
+            };
+            // Only opened (beneath a secure directory) once there's an ACL to set on it
+            if target.is_none() {
+                target = Some(xattr_target(dest)?.ok_or_else(|| no_xattr_target(&dest, true))?);
+            }
+            xattr::set_value(target.as_ref().unwrap(), name, &value)
+                .map_err(make_error_message!(dest))?;
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (source, dest);
+    Ok(())
+}
+
+/// Fail with the same error as creating `path` would if something already exists there.
+pub fn ensure_absent<P: AsPath>(path: P) -> Result<(), Error> {
+    let path = reach(&path);
+    match symlink_metadata(path) {
+        Ok(_) => Err(make_error_message!(path)(io::Error::from_raw_os_error(
+            libc::EEXIST,
+        ))),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Set the access and modification times of `path` to the current time.
+pub fn reset_timestamps<P: AsPath>(path: P) -> Result<(), Error> {
+    let path = reach(&path);
+    let now = TimeSpec::from(timespec {
+        tv_sec: 0,
+        tv_nsec: libc::UTIME_NOW,
+    });
+    let at = at(path).map_err(make_error_message!(path))?;
+    stat::utimensat(
+        at.fd(),
+        at.name,
+        &now,
+        &now,
+        UtimensatFlags::NoFollowSymlink,
+    )
+    .map_err(make_error_message!(path))
 }
 
 #[derive(Debug)]
 pub enum FileType {
-    Regular,
+    Regular(Metadata),
     Directory(Metadata),
-    Symlink,
+    Symlink(Metadata),
     Fifo(Metadata),
-    Socket,
+    Socket(Metadata),
     CharacterDevice(Metadata),
     BlockDevice(Metadata),
+    /// A file of a type other than the above, as may be found on unusual filesystems
+    Unknown(Metadata),
+}
+
+impl From<Metadata> for FileType {
+    /// Classify a file by its metadata (as retrieved without following symlinks, for it to be
+    /// classified as one).
+    fn from(metadata: Metadata) -> Self {
+        classify(metadata)
+    }
+}
+
+impl FileType {
+    /// The metadata of the file.
+    pub fn metadata(&self) -> &Metadata {
+        match self {
+            FileType::Regular(metadata)
+            | FileType::Directory(metadata)
+            | FileType::Symlink(metadata)
+            | FileType::Fifo(metadata)
+            | FileType::Socket(metadata)
+            | FileType::CharacterDevice(metadata)
+            | FileType::BlockDevice(metadata)
+            | FileType::Unknown(metadata) => metadata,
+        }
+    }
+
+    /// Whether this is a FIFO, socket, or device, rather than a regular file, directory, or
+    /// symlink (or a file of an unknown type).
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            FileType::Fifo(_)
+                | FileType::Socket(_)
+                | FileType::CharacterDevice(_)
+                | FileType::BlockDevice(_)
+        )
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FileType::Regular(_) => "regular file",
+            FileType::Directory(_) => "directory",
+            FileType::Symlink(_) => "symbolic link",
+            FileType::Fifo(_) => "fifo",
+            FileType::Socket(_) => "socket",
+            FileType::CharacterDevice(_) => "character device",
+            FileType::BlockDevice(_) => "block device",
+            FileType::Unknown(_) => "file of unknown type",
+        })
+    }
 }
 
 pub fn file_type(path: &Path) -> Result<FileType, Error> {
-    let metadata = symlink_metadata(path)?;
+    Ok(classify(symlink_metadata(path)?))
+}
+
+/// Like [`file_type`], but following `path` if it is a symlink (so the type is never `Symlink`,
+/// and the metadata is that of its target).
+pub fn dereferenced_file_type(path: &Path) -> Result<FileType, Error> {
+    let metadata = fs::metadata(path).map_err(|err| {
+        let dangling = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink());
+        let message = if dangling {
+            format!("{}: cannot dereference: {}", path.display(), err)
+        } else {
+            format!("{}: {}", path.display(), err)
+        };
+        Error::with_cause(err, path, message)
+    })?;
+    Ok(classify(metadata))
+}
+
+fn classify(metadata: Metadata) -> FileType {
     let file_type = metadata.file_type();
-    Ok(if file_type.is_file() {
-        FileType::Regular
+    if file_type.is_file() {
+        FileType::Regular(metadata)
     } else if file_type.is_dir() {
         FileType::Directory(metadata)
     } else if file_type.is_symlink() {
-        FileType::Symlink
+        FileType::Symlink(metadata)
     } else if file_type.is_fifo() {
         FileType::Fifo(metadata)
     } else if file_type.is_socket() {
-        FileType::Socket
+        FileType::Socket(metadata)
     } else if file_type.is_char_device() {
         FileType::CharacterDevice(metadata)
     } else if file_type.is_block_device() {
         FileType::BlockDevice(metadata)
     } else {
-        unreachable!(
-            "{}: file appears to exist but is an unknown type",
-            path.display()
-        );
-    })
+        FileType::Unknown(metadata)
+    }
 }
@@ -0,0 +1,71 @@
+//! Interruption of a copy by SIGINT or SIGTERM, cleaning up the files left partially copied.
+
+use crate::filesystem::Error;
+use crate::Status;
+use nix::sys::signal::{SigSet, Signal};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A function cleaning up a file left partially copied.
+type Cleanup = Box<dyn FnOnce() + Send>;
+
+/// The files being copied, which are cleaned up should the copy be interrupted.
+#[derive(Default)]
+pub struct Interrupt {
+    interrupted: AtomicBool,
+    partial: Mutex<HashMap<PathBuf, Cleanup>>,
+}
+
+impl Interrupt {
+    /// Watch for SIGINT and SIGTERM on a dedicated thread, upon which every file still being
+    /// copied is cleaned up and the process exits with [`Status::Interrupted`]. The signals are
+    /// blocked in the calling thread, so this must be called before any other threads are spawned
+    /// from it (which inherit the blocked signals) for them not to interrupt the process directly.
+    pub fn watch(self: &Arc<Self>) -> Result<(), Error> {
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGINT);
+        signals.add(Signal::SIGTERM);
+        signals
+            .thread_block()
+            .map_err(|err| Error::new(format!("failed to block signals: {}", err)))?;
+        let interrupt = Arc::clone(self);
+        thread::spawn(move || {
+            if signals.wait().is_ok() {
+                interrupt.interrupted.store(true, Ordering::Relaxed);
+                // The lock is held until the process exits, so that no more files can be started
+                let mut partial = interrupt.partial.lock().unwrap();
+                for (_, cleanup) in partial.drain() {
+                    cleanup();
+                }
+                process::exit(Status::Interrupted as i32);
+            }
+        });
+        Ok(())
+    }
+
+    /// Whether the copy has been interrupted, in which case no more files are to be copied.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// Call `copy` to copy a file to `dest`, which is cleaned up by `cleanup` should the copy be
+    /// interrupted before `copy` returns.
+    pub fn guard<T>(
+        &self,
+        dest: &Path,
+        cleanup: impl FnOnce() + Send + 'static,
+        copy: impl FnOnce() -> T,
+    ) -> T {
+        self.partial
+            .lock()
+            .unwrap()
+            .insert(dest.to_path_buf(), Box::new(cleanup));
+        let result = copy();
+        self.partial.lock().unwrap().remove(dest);
+        result
+    }
+}
@@ -1,100 +1,2010 @@
+use nix::{libc, unistd};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use std::array;
+use rayon::ThreadPoolBuilder;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
-use std::fs::Metadata;
-use std::io;
-use std::ops::BitOr;
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::fs::{DirEntry, File, Metadata, Permissions, ReadDir};
+use std::io::{self, prelude::*};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
+mod backup;
 pub mod filesystem;
+pub mod interrupt;
+pub mod options;
+mod output;
+mod progress;
+mod prompt;
+mod symlink;
+mod throttle;
 
-use crate::filesystem::{self as fs, Error, FileType};
+use crate::filesystem::{self as fs, DestPath, Error, ErrorKind, FileType};
+use crate::interrupt::Interrupt;
+use crate::options::{Backup, Options, Preallocate, Reflink, Sparse, SymlinkTarget};
+use crate::output::{Kind, Output};
+use crate::progress::{Progress, Reporter};
+use crate::prompt::Prompt;
+use crate::throttle::Limiter;
+use glob::Pattern;
 
-pub fn fatal(message: impl Display) -> ! {
+/// The exit status of `fcp`, modelled loosely on those of GNU coreutils. When several errors
+/// occur, the status is that of the most severe of them (which is always the highest).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Success = 0,
+    /// Any error not covered by a more specific status
+    Failure = 1,
+    /// The command-line arguments were invalid
+    Usage = 2,
+    PermissionDenied = 13,
+    CrossDevice = 18,
+    /// The copy was interrupted by SIGINT or SIGTERM (as is conventional for shells)
+    Interrupted = 130,
+}
+
+impl From<ErrorKind> for Status {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::InvalidInput => Status::Usage,
+            ErrorKind::PermissionDenied => Status::PermissionDenied,
+            ErrorKind::CrossDevice => Status::CrossDevice,
+            _ => Status::Failure,
+        }
+    }
+}
+
+impl From<&Error> for Status {
+    fn from(err: &Error) -> Self {
+        err.kind().into()
+    }
+}
+
+pub fn fatal(message: impl Display, status: Status) -> ! {
     eprintln!("{}", message);
-    process::exit(1);
+    process::exit(status as i32);
+}
+
+/// The destination of the first link to a file with multiple hard links to have been copied, or
+/// `None` if copying it failed.
+type FirstLink = Arc<Mutex<Option<DestPath>>>;
+
+/// The state shared by every file being copied, alongside the options governing the copy.
+struct Context {
+    options: Options,
+    /// The first link copied for each (device, inode) pair of a file with multiple hard links. The
+    /// lock on each is held until the link has finished being copied, so that subsequent links to
+    /// it can wait for it to exist.
+    hard_links: Mutex<HashMap<(u64, u64), FirstLink>>,
+    /// The (device, inode) pair of each directory copied to, which is never copied itself should it
+    /// be reached as a source (e.g. through a symlink to a directory containing the destination)
+    copies: Mutex<HashSet<(u64, u64)>>,
+    /// The number of directory entries read but not yet copied, across every directory being
+    /// copied, which `--max-inflight` limits
+    inflight: AtomicUsize,
+    progress: Option<Arc<Progress>>,
+    output: Output,
+    /// Present only when copying interactively
+    prompt: Option<Prompt>,
+    /// Present only when the rate of the copy is limited
+    limiter: Option<Limiter>,
+    /// The process's umask, present only when the modes of sources aren't preserved
+    umask: Option<u32>,
+    callback: Option<Arc<dyn Fn(u64) + Send + Sync>>,
+    /// Present only when the copy can be interrupted
+    interrupt: Option<Arc<Interrupt>>,
+}
+
+impl Context {
+    fn new(
+        options: Options,
+        sources: &[PathBuf],
+        callback: Option<Arc<dyn Fn(u64) + Send + Sync>>,
+        interrupt: Option<Arc<Interrupt>>,
+        log: Option<File>,
+    ) -> Self {
+        let progress = if options.progress && !options.quiet {
+            Some(Arc::new(Progress::new(sources)))
+        } else {
+            None
+        };
+        Context {
+            output: Output::new(
+                options.verbose || options.dry_run,
+                options.output,
+                options.keep_going,
+                options.quiet,
+                log,
+            ),
+            prompt: if options.interactive {
+                Some(Prompt::new())
+            } else {
+                None
+            },
+            limiter: options.bwlimit.map(Limiter::new),
+            umask: if options.preserve.mode {
+                None
+            } else {
+                Some(fs::umask())
+            },
+            options,
+            hard_links: Mutex::new(HashMap::new()),
+            copies: Mutex::new(HashSet::new()),
+            inflight: AtomicUsize::new(0),
+            progress,
+            callback,
+            interrupt,
+        }
+    }
+
+    /// Call `copy` to copy a file to `dest`, which is cleaned up by `cleanup` should the copy be
+    /// interrupted before `copy` returns.
+    fn guard<T>(
+        &self,
+        dest: &Path,
+        cleanup: impl FnOnce() + Send + 'static,
+        copy: impl FnOnce() -> T,
+    ) -> T {
+        match &self.interrupt {
+            Some(interrupt) => interrupt.guard(dest, cleanup, copy),
+            None => copy(),
+        }
+    }
+
+    fn add_bytes(&self, bytes: u64) {
+        self.output.add_bytes(bytes);
+        if let Some(progress) = &self.progress {
+            progress.add_bytes(bytes);
+        }
+        if let Some(callback) = &self.callback {
+            callback(bytes);
+        }
+    }
+
+    /// Account for `bytes` of contents having been copied, waiting for as long as is needed for the
+    /// copy to stay within any limit on its rate.
+    /// The most bytes of a file to copy at a time, if limited so as to be throttled smoothly.
+    fn max_chunk(&self) -> Option<usize> {
+        self.limiter.as_ref().map(Limiter::chunk_len)
+    }
+
+    fn transfer(&self, bytes: u64) {
+        self.add_bytes(bytes);
+        if let Some(limiter) = &self.limiter {
+            limiter.consume(bytes);
+        }
+    }
+}
+
+/// A directory on the path from the root of a tree being copied to one of the files within it.
+struct Ancestor {
+    /// The (device, inode) pair of the directory
+    key: (u64, u64),
+    parent: Option<Arc<Ancestor>>,
+}
+
+impl Ancestor {
+    fn contains(&self, key: (u64, u64)) -> bool {
+        self.key == key
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.contains(key))
+    }
+}
+
+/// The position of a file being copied within the tree rooted at one of the sources. Nothing is
+/// borrowed from the directories containing the file, so that it can outlive the traversal of
+/// those directories on the stack of directories being copied (see [`copy_tree`]).
+#[derive(Clone)]
+struct Location {
+    /// The device of the source at the root of the tree, or `None` if this is the root itself
+    device: Option<u64>,
+    /// The innermost of the directories containing the file, which are only tracked while
+    /// dereferencing symlinks (in order to detect symlinks which form loops)
+    ancestors: Option<Arc<Ancestor>>,
+    /// The source at the root of the tree, or `None` if this is the root itself
+    root: Option<Arc<Path>>,
+    /// The copy of the source at the root of the tree, held open with `--secure-dest` so that the
+    /// directories beneath it are opened through it (see [`fs::SecureDirectory`]), or `None`
+    /// otherwise (or if this is the root itself)
+    dest_root: Option<Arc<fs::SecureDirectory>>,
+    /// The number of directories between the root of the tree and the file
+    depth: usize,
+}
+
+impl Location {
+    const ROOT: Location = Location {
+        device: None,
+        ancestors: None,
+        root: None,
+        dest_root: None,
+        depth: 0,
+    };
+
+    /// The location of each entry of the directory `source` at this location (described by
+    /// `metadata`), where `directory` is that directory if it's being tracked, and `dest_root` is
+    /// its copy if it's the root and that's held open.
+    fn entries(
+        self,
+        source: &Path,
+        metadata: &Metadata,
+        directory: Option<Ancestor>,
+        dest_root: Option<Arc<fs::SecureDirectory>>,
+    ) -> Location {
+        Location {
+            device: Some(self.device.unwrap_or(metadata.dev())),
+            ancestors: directory.map(Arc::new).or(self.ancestors),
+            root: Some(self.root.unwrap_or_else(|| Arc::from(source))),
+            dest_root: self.dest_root.or(dest_root),
+            depth: self.depth + 1,
+        }
+    }
+}
+
+/// Whether the file at `path` (within the tree rooted at `root`) is excluded from the copy: that
+/// is, whether any of the patterns to exclude matches it, and none of those to include does. Patterns
+/// containing a `/` are matched against the path relative to `root`, and others against the name
+/// of the file alone.
+fn is_excluded(path: &Path, root: &Path, options: &Options) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let name = path.file_name().map_or(relative, Path::new);
+    let matches = |pattern: &Pattern| {
+        if pattern.as_str().contains('/') {
+            pattern.matches_path(relative)
+        } else {
+            pattern.matches_path(name)
+        }
+    };
+    options.exclude.iter().any(matches) && !options.include.iter().any(matches)
+}
+
+fn is_directory(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Whether a file of type `file_type` should be copied to `dest`, which isn't the case if it
+/// already exists and either clobbering is disallowed or the user declines to overwrite it.
+/// Directories are never overwritten (merely merged into), so they're always copied.
+fn should_copy(file_type: &FileType, dest: &DestPath, context: &Context) -> bool {
+    let no_clobber = context.options.no_clobber || context.options.skip_existing;
+    if matches!(file_type, FileType::Directory(_))
+        || !no_clobber && context.prompt.is_none()
+        || fs::symlink_metadata(dest).is_err()
+    {
+        return true;
+    }
+    let overwrite = match &context.prompt {
+        Some(prompt) if !no_clobber => prompt.overwrite(dest),
+        _ => false,
+    };
+    if !overwrite {
+        if context.options.skip_existing {
+            context.output.skipped_existing(dest);
+        } else {
+            context.output.skipped(dest);
+        }
+    }
+    overwrite
+}
+
+/// Create `dest` (as a directory if `directory` is set) by calling `create`, first backing up any
+/// existing file (other than a directory) there if backups are enabled. If creation fails because
+/// of an existing file in the way and the copy is forced, that file is made writable (if it's a
+/// read-only regular file) or else removed, and `create` is called once more. Existing directories
+/// are only removed to make way for files which aren't directories themselves. Otherwise, an
+/// existing file which is a directory where `dest` isn't to be (or vice versa) fails to be
+/// overwritten with an error saying as much.
+fn create_forcibly<T>(
+    dest: &DestPath,
+    directory: bool,
+    context: &Context,
+    create: impl Fn() -> Result<T, Error>,
+) -> Result<T, Error> {
+    if context.options.backup != Backup::None {
+        backup::back_up(dest, context.options.backup)?;
+    }
+    let err = match create() {
+        Err(err)
+            if context.options.force
+                && (matches!(
+                    err.kind(),
+                    ErrorKind::PermissionDenied | ErrorKind::AlreadyExists
+                ) || err.raw_os_error() == Some(libc::EISDIR)) =>
+        {
+            err
+        }
+        Err(err)
+            if err.kind() == ErrorKind::AlreadyExists
+                || err.raw_os_error() == Some(libc::EISDIR) =>
+        {
+            return Err(match fs::symlink_metadata(dest) {
+                Ok(existing) if existing.is_dir() != directory => {
+                    Error::type_conflict(dest, existing.is_dir())
+                }
+                _ => err,
+            });
+        }
+        result => return result,
+    };
+    // Read-only files are made writable to be overwritten in place where possible (keeping any
+    // other links to them), and are otherwise removed like any other file in the way
+    if err.kind() == ErrorKind::PermissionDenied && !directory {
+        if let Ok(existing) = fs::symlink_metadata(dest) {
+            let writable = Permissions::from_mode(existing.permissions().mode() | 0o200);
+            if existing.is_file() && fs::set_permissions(dest, writable).is_ok() {
+                match create() {
+                    Ok(created) => return Ok(created),
+                    Err(_) => {
+                        let _ = fs::set_permissions(dest, existing.permissions());
+                    }
+                }
+            }
+        }
+    }
+    match fs::symlink_metadata(dest) {
+        Ok(existing) if !existing.is_dir() => fs::remove_file(dest)?,
+        Ok(_) if !directory => fs::remove_dir_all(dest)?,
+        _ => return Err(err),
+    }
+    create()
+}
+
+/// Create `dest` (a file other than a regular file or directory) by calling `create` as with
+/// [`create_forcibly`], except that an existing file of the same type (as determined by
+/// `same_type`) is replaced even if the copy isn't forced. Such files can't be copied over in place
+/// as regular files are, and so copying a tree over an earlier copy of it would otherwise fail.
+fn replace_forcibly(
+    dest: &DestPath,
+    context: &Context,
+    same_type: impl Fn(&std::fs::FileType) -> bool,
+    create: impl Fn() -> Result<(), Error>,
+) -> Result<(), Error> {
+    create_forcibly(dest, false, context, || match create() {
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => match fs::symlink_metadata(dest) {
+            Ok(existing) if same_type(&existing.file_type()) => {
+                fs::remove_file(dest)?;
+                create()
+            }
+            _ => Err(err),
+        },
+        result => result,
+    })
+}
+
+/// How long to wait before retrying a file after its first transient error, which doubles for each
+/// subsequent retry.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Whether `err` may well not occur again were the operation which failed with it retried (e.g. a
+/// network filesystem timing out), as opposed to it stemming from the state of the files involved.
+fn is_transient(err: &Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(
+            libc::EIO
+                | libc::ETIMEDOUT
+                | libc::EAGAIN
+                | libc::EBUSY
+                | libc::ECONNRESET
+                | libc::ENETRESET
+                | libc::ESTALE
+        )
+    )
+}
+
+/// Check that `dest` exists to copy the attributes of a file of type `file_type` to, and that it's a
+/// directory only if that file is.
+fn ensure_existing(dest: &DestPath, file_type: &FileType) -> Result<(), Error> {
+    let existing = fs::symlink_metadata(dest)?;
+    if existing.is_dir() != matches!(file_type, FileType::Directory(_)) {
+        return Err(Error::type_conflict(dest, existing.is_dir()));
+    }
+    Ok(())
+}
+
+/// Whether `dest` is an existing regular file of the same size as the source `source` (described by
+/// `metadata`), with the same contents. Failing to compare them leaves the copy to be made (and to
+/// fail, if it must).
+fn is_identical(source: &Path, metadata: &Metadata, dest: &DestPath) -> bool {
+    match fs::symlink_metadata(dest) {
+        Ok(existing) if existing.is_file() && existing.len() == metadata.len() => {
+            fs::identical(source, dest).unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `dest` doesn't exist or was last modified before the source (described by `metadata`).
+fn is_outdated(dest: &DestPath, metadata: &Metadata) -> bool {
+    let dest = match fs::symlink_metadata(dest) {
+        Ok(dest) => dest,
+        Err(_) => return true,
+    };
+    match (dest.modified(), metadata.modified()) {
+        (Ok(dest), Ok(source)) => dest < source,
+        _ => true,
+    }
+}
+
+/// Remove the regular file `dest` after failing to copy to it, so that the partial copy can't be
+/// mistaken for a complete one, unless it's the file which was there before (described by
+/// `existing`) left untouched (e.g. as it couldn't be opened).
+fn unwind(dest: &DestPath, existing: Option<&Metadata>) {
+    if is_partial(dest, existing) {
+        let _ = fs::remove_file(dest);
+    }
+}
+
+/// Mark the regular file `dest` left partially copied by a failed copy as resumable by giving it
+/// the timestamps of its source (described by `metadata`), unless it's the file which was there
+/// before (described by `existing`) left untouched.
+fn leave_partial(dest: &DestPath, metadata: &Metadata, existing: Option<&Metadata>) {
+    if is_partial(dest, existing) {
+        let _ = fs::set_timestamps(dest, metadata);
+    }
 }
 
-// The boolean returned signifies whether an error occurred (`true`) or not (`false`). The purpose
-// of returning just a boolean instead of the underlying error itself is that we want to display
-// the error to the user as soon as it occurs (as this makes for a better user-experience during
+/// Clean up the regular file `dest` after failing to copy to it, by either leaving it to be resumed
+/// (if `resume` is set) or removing it.
+fn abandon(dest: &DestPath, metadata: &Metadata, existing: Option<&Metadata>, resume: bool) {
+    if resume {
+        leave_partial(dest, metadata, existing);
+    } else {
+        unwind(dest, existing);
+    }
+}
+
+/// Whether `dest` is a regular file other than the one which was there before a copy to it
+/// (described by `existing`) was attempted.
+fn is_partial(dest: &DestPath, existing: Option<&Metadata>) -> bool {
+    let identity = |metadata: &Metadata| {
+        (
+            metadata.dev(),
+            metadata.ino(),
+            metadata.len(),
+            metadata.mtime(),
+            metadata.mtime_nsec(),
+        )
+    };
+    match fs::symlink_metadata(dest) {
+        Ok(current) => current.is_file() && existing.map(identity) != Some(identity(&current)),
+        Err(_) => false,
+    }
+}
+
+/// The length of the partial copy at `dest` of `source` (described by `metadata`), if it can be
+/// resumed: that is, if it's a regular file no longer than its source whose contents already
+/// copied are either verified to match the start of the source (if `checksum` is set) or else
+/// trusted to, if it has the same modification time as the source (as partial copies are left
+/// with by [`leave_partial`], such that the source is presumed not to have changed since).
+fn resumable_length(
+    source: &Path,
+    metadata: &Metadata,
+    dest: &DestPath,
+    checksum: bool,
+) -> Option<u64> {
+    let dest_metadata = fs::symlink_metadata(dest).ok()?;
+    let len = dest_metadata.len();
+    if !dest_metadata.is_file() || len > metadata.len() {
+        return None;
+    }
+    let resumable = if checksum {
+        fs::verify_prefix(source, dest, len).unwrap_or(false)
+    } else {
+        (dest_metadata.mtime(), dest_metadata.mtime_nsec())
+            == (metadata.mtime(), metadata.mtime_nsec())
+    };
+    if resumable {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The permissions to give the copy of a file (described by `metadata`): its own, unless modes
+/// aren't being preserved, with any `--chmod` applied.
+fn permissions(metadata: &Metadata, context: &Context) -> Permissions {
+    let permissions = match context.umask {
+        None => metadata.permissions(),
+        Some(umask) if metadata.is_dir() => Permissions::from_mode(0o777 & !umask),
+        Some(umask) => Permissions::from_mode(0o666 & !umask),
+    };
+    // Applied to whichever mode the destination would otherwise be given
+    match &context.options.chmod {
+        Some(chmod) => Permissions::from_mode(chmod.apply(permissions.mode(), metadata.is_dir())),
+        None => permissions,
+    }
+}
+
+fn preserve_attributes(
+    source: &Path,
+    dest: &DestPath,
+    metadata: &Metadata,
+    set_mode: bool,
+    context: &Context,
+) -> Result<(), Error> {
+    if context.options.preserve.ownership {
+        // Failing to change ownership (most commonly because the process lacks the privilege to)
+        // shouldn't prevent the rest of the copy from proceeding.
+        if let Err(err) = fs::set_ownership(dest, metadata) {
+            context.output.warning(err);
+        }
+    }
+    // Extended attributes are copied after changing ownership, as doing so clears some of them
+    // (e.g. `security.capability`).
+    let xattrs = if context.options.preserve.xattr {
+        fs::copy_xattrs(source, dest).map(|skipped| {
+            for err in skipped {
+                context.output.warning(err);
+            }
+        })
+    } else {
+        Ok(())
+    };
+    // The mode is set once the contents have been written and ownership changed, as either can
+    // clear the setuid and setgid bits (symlinks have no mode of their own to set). This is done
+    // even if copying the extended attributes failed, lest a directory be left with the
+    // owner-writable mode it was created with.
+    if set_mode && !metadata.file_type().is_symlink() {
+        fs::set_permissions(dest, permissions(metadata, context))?;
+    }
+    xattrs?;
+    // The ACLs are applied after the mode, as doing so in the opposite order would clobber the
+    // group permissions the ACLs set.
+    if context.options.preserve.acl {
+        fs::copy_acls(source, dest)?;
+    }
+    if context.options.preserve.timestamps {
+        fs::set_timestamps(dest, metadata)?;
+    }
+    Ok(())
+}
+
+fn copy_regular_file(
+    source: &Path,
+    metadata: &Metadata,
+    dest: &DestPath,
+    context: &Context,
+) -> Result<(), Error> {
+    // The files opened in attempting to clone them are copied between in turn, rather than being
+    // opened all over again
+    let mut opened = None;
+    // Created with its final permissions from the start (albeit without the setuid, setgid, and
+    // sticky bits until it's finished), so that it's never accessible more widely than they allow
+    let mode = permissions(metadata, context).mode() & 0o777;
+    if context.options.reflink != Reflink::Never {
+        match fs::clone(source, dest, mode)? {
+            fs::Cloned::Cloned => {
+                context.add_bytes(metadata.len());
+                // Clones inherit the timestamps of their source, which would otherwise be those of
+                // when the copy was made.
+                if !context.options.preserve.timestamps {
+                    fs::reset_timestamps(dest)?;
+                }
+                return Ok(());
+            }
+            fs::Cloned::Unsupported(files) => opened = files,
+        }
+        if context.options.reflink == Reflink::Always {
+            return Err(Error::new(format!(
+                "{}: failed to clone to {}",
+                source.display(),
+                dest.display()
+            )));
+        }
+    }
+    let sparse = match context.options.sparse {
+        Sparse::Auto => fs::is_sparse(metadata),
+        Sparse::Always => true,
+        Sparse::Never => false,
+    };
+    // Smaller files are written in few enough pieces that they're hardly fragmented regardless
+    const PREALLOCATE_THRESHOLD: u64 = 1 << 20;
+    let preallocate = match context.options.preallocate {
+        Preallocate::Auto => metadata.len() >= PREALLOCATE_THRESHOLD,
+        Preallocate::Always => true,
+        Preallocate::Never => false,
+    };
+    // Copies within the kernel may share the underlying data as a clone does (e.g. on btrfs), so
+    // with cloning ruled out, the contents are only ever read and written
+    let in_kernel = context.options.reflink != Reflink::Never;
+    let progress = |bytes| context.transfer(bytes);
+    if let Some(threshold) = context.options.parallel_file_threshold {
+        if metadata.len() >= threshold {
+            let chunks = context
+                .options
+                .parallel_file_chunks
+                .unwrap_or_else(rayon::current_num_threads);
+            fs::copy_parallel(
+                source,
+                dest,
+                mode,
+                opened,
+                chunks,
+                context.options.buffer_size,
+                context.max_chunk(),
+                sparse,
+                preallocate,
+                progress,
+            )?;
+            return Ok(());
+        }
+    }
+    if sparse {
+        let copied = fs::copy_sparse(
+            source,
+            dest,
+            mode,
+            opened,
+            context.options.buffer_size,
+            context.max_chunk(),
+            in_kernel,
+            progress,
+        )?;
+        // Account for the holes which were skipped over
+        context.add_bytes(metadata.len().saturating_sub(copied));
+    } else if context.options.direct {
+        // Opened afresh with `O_DIRECT`
+        drop(opened);
+        fs::copy_direct(
+            source,
+            dest,
+            mode,
+            context.options.buffer_size,
+            context.max_chunk(),
+            in_kernel,
+            progress,
+        )?;
+    } else if context.options.mmap {
+        // Files which can't be mapped are copied as they otherwise would be
+        if fs::copy_mapped(source, dest, mode, opened, context.max_chunk(), progress)?.is_none() {
+            fs::copy(
+                source,
+                dest,
+                mode,
+                None,
+                context.options.buffer_size,
+                context.max_chunk(),
+                preallocate,
+                in_kernel,
+                progress,
+            )?;
+        }
+    } else {
+        fs::copy(
+            source,
+            dest,
+            mode,
+            opened,
+            context.options.buffer_size,
+            context.max_chunk(),
+            preallocate,
+            in_kernel,
+            progress,
+        )?;
+    }
+    Ok(())
+}
+
+/// Copy the regular file `source` to a temporary file alongside `dest`, which is then renamed
+/// over it once complete, so that `dest` is never seen partially copied. The temporary file is
+/// removed should the copy fail.
+fn copy_atomically(
+    source: &Path,
+    metadata: &Metadata,
+    dest: &DestPath,
+    context: &Context,
+) -> Result<(), Error> {
+    let mut name = OsString::from(".");
+    name.push(dest.file_name().unwrap_or_default());
+    name.push(format!(".fcp.tmp.{}", process::id()));
+    let temporary = dest.with_file_name(name);
+    let cleanup = {
+        let temporary = temporary.clone();
+        move || {
+            let _ = fs::remove_file(&temporary);
+        }
+    };
+    let result = context
+        .guard(&temporary, cleanup, || {
+            copy_regular_file(source, metadata, &temporary, context)
+        })
+        // Otherwise, the rename could persist through a crash without the contents it exposes
+        .and_then(|()| {
+            if context.options.fsync {
+                fs::sync(&temporary)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|()| create_forcibly(dest, false, context, || fs::rename(&temporary, dest)));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Copy the regular file `source` to `dest`, unless hard links are being preserved and `source` is
+/// a link to a file which has already been copied, in which case `dest` is instead created as a
+/// hard link to that copy, or `--link` was given, in which case `dest` is created as a hard link to
+/// `source` itself where possible (or `--symbolic-link`, in which case it's created as a symlink to
+/// `source`). Returns whether `dest` was created as a link.
+fn copy_or_link(
+    source: &Path,
+    metadata: &Metadata,
+    dest: &DestPath,
+    context: &Context,
+) -> Result<bool, Error> {
+    let copy = || {
+        if context.options.atomic {
+            return copy_atomically(source, metadata, dest, context);
+        }
+        let existing = fs::symlink_metadata(dest).ok();
+        let resume = context.options.resume;
+        let result = create_forcibly(dest, false, context, || {
+            // Partial copies are left in place to be resumed, even if interrupted
+            let cleanup = {
+                let (dest, metadata, existing) = (dest.clone(), metadata.clone(), existing.clone());
+                move || abandon(&dest, &metadata, existing.as_ref(), resume)
+            };
+            context.guard(dest, cleanup, || {
+                copy_regular_file(source, metadata, dest, context)
+            })
+        });
+        if result.is_err() {
+            abandon(dest, metadata, existing.as_ref(), resume);
+        }
+        result
+    };
+    if context.options.symbolic_link {
+        let target = symlink::to_source(source, dest, context.options.relative)?;
+        create_forcibly(dest, false, context, || {
+            fs::copy_symlink(source, &target, dest)
+        })?;
+        context.add_bytes(metadata.len());
+        return Ok(true);
+    }
+    if context.options.link {
+        match create_forcibly(dest, false, context, || fs::link(source, dest)) {
+            Ok(()) => {
+                context.add_bytes(metadata.len());
+                return Ok(true);
+            }
+            // Links can't span filesystems or exceed the maximum number of links to a file, so such
+            // files are copied instead
+            Err(err)
+                if err.kind() == ErrorKind::CrossDevice
+                    || err.raw_os_error() == Some(libc::EMLINK) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    if !context.options.preserve.links || metadata.nlink() <= 1 {
+        copy()?;
+        return Ok(false);
+    }
+    let key = (metadata.dev(), metadata.ino());
+    let mut hard_links = context.hard_links.lock().unwrap();
+    if let Some(first) = hard_links.get(&key).cloned() {
+        drop(hard_links);
+        // Blocks until the first link has finished being copied
+        if let Some(first_dest) = &*first.lock().unwrap() {
+            create_forcibly(dest, false, context, || fs::hard_link(first_dest, dest))?;
+            context.add_bytes(metadata.len());
+            return Ok(true);
+        }
+        // Copying the first link failed, so there's nothing to link to
+        copy()?;
+        return Ok(false);
+    }
+    let first = Arc::new(Mutex::new(None));
+    let mut first_dest = first.lock().unwrap();
+    hard_links.insert(key, Arc::clone(&first));
+    drop(hard_links);
+    copy()?;
+    *first_dest = Some(dest.detach());
+    Ok(false)
+}
+
+// The status returned is that of the most severe error which occurred, if any. The purpose of
+// returning just a status instead of the underlying error itself is that we want to display the
+// error to the user as soon as it occurs (as this makes for a better user-experience during
 // long-running jobs) as opposed to propagating it upwards and printing all errors at the end.
-// However, at the end of the process we still need to know whether or not an error occurred at any
-// point in order to set the exit code appropriately.
-fn copy_file(source: &Path, dest: &Path) -> bool {
-    fn __copy_file(source: &Path, dest: &Path) -> Result<bool, Error> {
-        match fs::file_type(source)? {
-            FileType::Regular => {
-                fs::copy(source, dest)?;
-            }
-            FileType::Directory(metadata) => return copy_directory((source, metadata), dest),
-            FileType::Symlink => fs::symlink(fs::read_link(source)?, dest)?,
-            FileType::Fifo(metadata) => fs::mkfifo(dest, metadata.permissions())?,
-            FileType::Socket => {
+// However, at the end of the process we still need to know which errors occurred at any point in
+// order to set the exit code appropriately.
+fn copy_file(source: &Path, dest: &DestPath, location: Location, context: &Context) -> Status {
+    match start_copy(source, None, dest, location, context) {
+        Started::Finished(status) => status,
+        Started::Directory(directory) => copy_tree(*directory, context),
+    }
+}
+
+/// A directory which has been created, but whose entries have yet to be copied, after which its
+/// attributes are preserved (as those of any other file are once it's been copied).
+struct Directory {
+    source: PathBuf,
+    dest: DestPath,
+    /// The metadata of `source`, or `None` if its attributes aren't to be preserved
+    metadata: Option<Metadata>,
+    set_mode: bool,
+    /// The location of the entries of the directory, or `None` if they aren't to be copied
+    contents: Option<Location>,
+}
+
+/// The outcome of starting to copy a file with [`start_copy`].
+enum Started {
+    /// The file has been copied, or failed to be, with the given status
+    Finished(Status),
+    /// The file is a directory, whose entries are yet to be copied
+    Directory(Box<Directory>),
+}
+
+/// Copy `source` to `dest`, unless it's a directory, in which case `dest` is merely created, to be
+/// finished by copying the entries of the directory into it (with [`copy_tree`]). `metadata` is
+/// that of `source` (without following symlinks) if it's already known, sparing it another stat.
+fn start_copy(
+    source: &Path,
+    mut metadata: Option<Metadata>,
+    dest: &DestPath,
+    location: Location,
+    context: &Context,
+) -> Started {
+    fn __copy_file(
+        source: &Path,
+        metadata: Option<Metadata>,
+        dest: &DestPath,
+        location: Location,
+        context: &Context,
+    ) -> Result<Option<Directory>, Error> {
+        let dry_run = context.options.dry_run;
+        // Symlinks given as sources have no root, as they're at the root of their own trees
+        let dereference = context.options.dereference
+            || context.options.dereference_arguments && location.root.is_none();
+        let file_type = match metadata {
+            // Only symlinks have metadata of their own distinct from that of the file they're
+            // dereferenced to
+            Some(metadata) if !dereference || !metadata.file_type().is_symlink() => {
+                FileType::from(metadata)
+            }
+            _ if dereference => fs::dereferenced_file_type(source)?,
+            _ => fs::file_type(source)?,
+        };
+        if !should_copy(&file_type, dest, context) {
+            return Ok(None);
+        }
+        let kind = Kind::from(&file_type);
+        if context.options.attributes_only {
+            ensure_existing(dest, &file_type)?;
+        }
+        let metadata = match file_type {
+            // Only the attributes of existing destinations are updated, leaving their contents as
+            // they are (directories are still traversed, to update the files within them)
+            FileType::Regular(metadata)
+            | FileType::Symlink(metadata)
+            | FileType::Fifo(metadata)
+            | FileType::CharacterDevice(metadata)
+            | FileType::BlockDevice(metadata)
+                if context.options.attributes_only =>
+            {
+                context.output.copied(kind, source, dest, 0);
+                if dry_run {
+                    None
+                } else {
+                    Some(metadata)
+                }
+            }
+            // The metadata was retrieved before copying, as reading the source updates its access
+            // time
+            FileType::Regular(metadata) => {
+                if context.options.update && !is_outdated(dest, &metadata) {
+                    context.output.skipped(dest);
+                    return Ok(None);
+                }
+                if context.options.compare_content && is_identical(source, &metadata, dest) {
+                    context.output.skipped_identical(dest);
+                    return Ok(None);
+                }
+                // Regular files are the only type of file which is overwritten if it already
+                // exists
+                if dry_run {
+                    context.output.copied(kind, source, dest, metadata.len());
+                    return Ok(None);
+                }
+                let resumable = if context.options.resume {
+                    resumable_length(source, &metadata, dest, context.options.checksum)
+                } else {
+                    None
+                };
+                let linked = match resumable {
+                    // Already verified to be complete if checksums are being compared
+                    Some(copied) if copied == metadata.len() => {
+                        context.output.skipped(dest);
+                        return Ok(None);
+                    }
+                    Some(copied) => {
+                        context.add_bytes(copied);
+                        let result = fs::copy_from(
+                            source,
+                            dest,
+                            copied,
+                            context.options.buffer_size,
+                            context.max_chunk(),
+                            context.options.reflink != Reflink::Never,
+                            |bytes| context.transfer(bytes),
+                        );
+                        if result.is_err() {
+                            leave_partial(dest, &metadata, None);
+                        }
+                        result?;
+                        false
+                    }
+                    None => copy_or_link(source, &metadata, dest, context)?,
+                };
+                if context.options.checksum && !linked {
+                    fs::verify(source, dest)?;
+                }
+                // Like the advice itself, failing to give it is of no consequence
+                if context.options.fadvise && !linked {
+                    let _ = fs::drop_cache(source);
+                    let _ = fs::drop_cache(dest);
+                }
+                context.output.copied(kind, source, dest, metadata.len());
+                // Linked files share their attributes with the file they're linked to
+                if linked {
+                    None
+                } else {
+                    Some(metadata)
+                }
+            }
+            FileType::Directory(metadata)
+                if context
+                    .copies
+                    .lock()
+                    .unwrap()
+                    .contains(&(metadata.dev(), metadata.ino())) =>
+            {
+                context.output.warning(format_args!(
+                    "omitting '{}', which is a copy being made",
+                    source.display()
+                ));
+                return Ok(None);
+            }
+            FileType::Directory(metadata) => {
+                // The mode of an existing directory which is merged into is left as it is, unless
+                // only attributes are being copied
+                let set_mode = !is_directory(dest) || context.options.attributes_only;
+                let contents = start_directory((source, &metadata), dest, location, context)?;
+                return Ok(Some(Directory {
+                    source: source.to_path_buf(),
+                    dest: dest.clone(),
+                    metadata: if dry_run { None } else { Some(metadata) },
+                    set_mode,
+                    contents,
+                }));
+            }
+            FileType::Socket(_) if context.options.no_ignore_special => {
                 return Err(Error::new(format!(
                     "{}: sockets cannot be copied",
                     source.display(),
                 )));
             }
-            FileType::CharacterDevice(metadata) | FileType::BlockDevice(metadata) => {
-                let mut source = fs::open(source)?;
-                let mut dest = fs::create(dest, metadata.permissions().mode())?;
-                io::copy(&mut source, &mut dest)?;
+            FileType::Socket(_) => {
+                context
+                    .output
+                    .warning(format_args!("omitting socket '{}'", source.display()));
+                return Ok(None);
+            }
+            FileType::Unknown(_) if context.options.no_ignore_special => {
+                return Err(Error::new(format!(
+                    "{}: files of unknown type cannot be copied",
+                    source.display(),
+                )));
+            }
+            FileType::Unknown(_) => {
+                context.output.warning(format_args!(
+                    "omitting '{}' of unknown type",
+                    source.display()
+                ));
+                return Ok(None);
+            }
+            _ if dry_run => {
+                if !context.options.force {
+                    fs::ensure_absent(dest)?;
+                }
+                context.output.copied(kind, source, dest, 0);
+                None
+            }
+            FileType::Symlink(metadata) => {
+                let metadata = Some(metadata).filter(|_| context.options.preserve.any());
+                let mut target = fs::read_link(source)?;
+                if let Some(root) = location
+                    .root
+                    .as_deref()
+                    .filter(|_| context.options.rewrite_symlinks)
+                {
+                    target = symlink::within_tree(source, &target, root)?;
+                }
+                target = match context.options.symlink_target {
+                    SymlinkTarget::Keep => target,
+                    SymlinkTarget::Relative => symlink::to_relative(dest, &target)?,
+                    SymlinkTarget::Absolute => symlink::to_absolute(dest, &target)?,
+                };
+                replace_forcibly(dest, context, std::fs::FileType::is_symlink, || {
+                    fs::copy_symlink(source, &target, dest)
+                })?;
+                context.output.copied(kind, source, dest, 0);
+                metadata
+            }
+            FileType::Fifo(metadata) if !context.options.copy_contents => {
+                replace_forcibly(dest, context, FileTypeExt::is_fifo, || {
+                    fs::mkfifo(dest, metadata.permissions())
+                })?;
+                context.output.copied(kind, source, dest, 0);
+                Some(metadata)
+            }
+            FileType::BlockDevice(metadata) if context.options.copy_device_contents => {
+                let mut dest_file = create_forcibly(dest, false, context, || {
+                    fs::create(dest, metadata.permissions().mode())
+                })?;
+                let bytes = fs::copy_device(
+                    source,
+                    &mut dest_file,
+                    context.options.buffer_size,
+                    |bytes| context.transfer(bytes),
+                )?;
+                if context.options.fsync {
+                    dest_file.sync_all()?;
+                }
+                context.output.copied(kind, source, dest, bytes);
+                Some(metadata)
             }
+            FileType::CharacterDevice(metadata) | FileType::BlockDevice(metadata)
+                if context.options.preserve.special =>
+            {
+                let same_type = |existing: &std::fs::FileType| {
+                    existing.is_char_device() == metadata.file_type().is_char_device()
+                        && existing.is_block_device() == metadata.file_type().is_block_device()
+                };
+                replace_forcibly(dest, context, same_type, || fs::mknod(dest, &metadata))?;
+                context.output.copied(kind, source, dest, 0);
+                Some(metadata)
+            }
+            FileType::Fifo(metadata)
+            | FileType::CharacterDevice(metadata)
+            | FileType::BlockDevice(metadata) => {
+                let mut source_file = fs::open(source)?;
+                let mut dest_file = create_forcibly(dest, false, context, || {
+                    fs::create(dest, metadata.permissions().mode())
+                })?;
+                let bytes = io::copy(&mut source_file, &mut dest_file)?;
+                if context.options.fsync {
+                    dest_file.sync_all()?;
+                }
+                context.add_bytes(bytes);
+                context.output.copied(kind, source, dest, bytes);
+                Some(metadata)
+            }
+        };
+        finish_copy(source, dest, metadata.as_ref(), true, kind, context)?;
+        Ok(None)
+    }
+
+    if context
+        .interrupt
+        .as_ref()
+        .is_some_and(|interrupt| interrupt.is_interrupted())
+    {
+        return Started::Finished(Status::Interrupted);
+    }
+    let mut attempt = 0;
+    let result = loop {
+        // Retries stat the source afresh, as whatever failed may well have changed it
+        match __copy_file(source, metadata.take(), dest, location.clone(), context) {
+            // Retrying a directory would copy everything within it over again
+            Err(err)
+                if attempt < context.options.retries
+                    && is_transient(&err)
+                    && !is_directory(source) =>
+            {
+                let delay = RETRY_DELAY * 2u32.saturating_pow(attempt);
+                context.output.warning(format_args!(
+                    "{} (retrying in {:.1} s)",
+                    err,
+                    delay.as_secs_f64()
+                ));
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => break result,
         }
-        Ok(false)
+    };
+    let status = match result {
+        Ok(Some(directory)) => return Started::Directory(Box::new(directory)),
+        Ok(None) => Status::Success,
+        Err(err) => context.output.error(err),
+    };
+    if let Some(progress) = &context.progress {
+        progress.add_file();
     }
+    Started::Finished(status)
+}
 
-    __copy_file(source, dest).unwrap_or_else(|err| {
-        eprintln!("{}", err);
-        true
-    })
+/// Finish copying `source` (a file of kind `kind`, described by `metadata` if its attributes are
+/// to be preserved) to `dest` once its contents have been copied, preserving its attributes and
+/// syncing `dest` if required.
+fn finish_copy(
+    source: &Path,
+    dest: &DestPath,
+    metadata: Option<&Metadata>,
+    set_mode: bool,
+    kind: Kind,
+    context: &Context,
+) -> Result<(), Error> {
+    if let Some(metadata) = metadata {
+        preserve_attributes(source, dest, metadata, set_mode, context)?;
+        // Like ownership, the source's access time is restored only on a best-effort basis
+        // (doing so requires owning it).
+        if context.options.atime_preserve && !metadata.file_type().is_symlink() {
+            if let Err(err) = fs::restore_atime(source, metadata) {
+                context.output.warning(err);
+            }
+        }
+    }
+    // Directories are synced once every entry within them has been copied, persisting those
+    // entries (including symlinks, which can't be synced themselves)
+    if context.options.fsync
+        && !context.options.dry_run
+        && matches!(kind, Kind::Regular | Kind::Directory)
+    {
+        fs::sync(dest)?;
+    }
+    Ok(())
 }
 
-fn copy_directory(source: (&Path, Metadata), dest: &Path) -> Result<bool, Error> {
+// Timestamps for the directory itself are set only once its entries have been copied (by
+// `copy_tree`), as copying each entry into `dest` updates its modification time. Returns the
+// location of those entries, or `None` if they aren't to be copied.
+fn start_directory(
+    source: (&Path, &Metadata),
+    dest: &DestPath,
+    location: Location,
+    context: &Context,
+) -> Result<Option<Location>, Error> {
     let (source, metadata) = source;
-    fs::create_dir(dest, metadata.permissions().mode())?;
-    Ok(fs::read_dir(source)?
-        .collect::<Box<_>>()
+    let key = (metadata.dev(), metadata.ino());
+    if location
+        .ancestors
+        .as_ref()
+        .is_some_and(|ancestors| ancestors.contains(key))
+    {
+        return Err(Error::new(format!(
+            "symlink loop detected at '{}'",
+            source.display()
+        )));
+    }
+    // Existing directories are merged into, whereas new ones are created writable by their owner
+    // so that their entries can be copied into them, and are only given their modes once finished
+    if context.options.dry_run {
+        if !is_directory(dest) && !context.options.force {
+            fs::ensure_absent(dest)?;
+        }
+    } else {
+        create_forcibly(dest, true, context, || {
+            match fs::create_dir(dest, metadata.permissions().mode() | 0o700) {
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && is_directory(dest) => Ok(()),
+                result => result,
+            }
+        })?;
+        let copy = fs::symlink_metadata(dest)?;
+        context
+            .copies
+            .lock()
+            .unwrap()
+            .insert((copy.dev(), copy.ino()));
+    }
+    context.output.copied(Kind::Directory, source, dest, 0);
+    // Mount points are copied as empty directories, without descending into them
+    if context.options.one_file_system
+        && location
+            .device
+            .is_some_and(|device| device != metadata.dev())
+    {
+        return Ok(None);
+    }
+    // As are directories at the maximum depth
+    if context
+        .options
+        .max_depth
+        .is_some_and(|max_depth| location.depth >= max_depth)
+    {
+        return Ok(None);
+    }
+    let directory = context.options.dereference.then(|| Ancestor {
+        key,
+        parent: location.ancestors.clone(),
+    });
+    // Opened without following symlinks, so that should the directory have been replaced with one
+    // since it was created, nothing is copied through it
+    let dest_root = if context.options.secure_dest
+        && !context.options.dry_run
+        && location.dest_root.is_none()
+    {
+        Some(fs::SecureDirectory::open(dest)?)
+    } else {
+        None
+    };
+    Ok(Some(
+        location.entries(source, metadata, directory, dest_root),
+    ))
+}
+
+/// Directories at least this many levels below the root of a tree have the directories within
+/// them copied in turn by the thread which reached them, rather than concurrently, as each level of
+/// concurrency adds to the (fixed) stack of the thread copying it.
+const MAX_CONCURRENT_DEPTH: usize = 32;
+
+/// The state of reading the entries of a directory on the stack of [`copy_tree`].
+enum Entries {
+    Unread,
+    Reading(ReadDir),
+    Read,
+}
+
+/// A directory on the stack of [`copy_tree`], which remains on it until every entry within it has
+/// been copied.
+struct Frame {
+    directory: Directory,
+    entries: Entries,
+    /// The status of the most severe error which occurred within the directory
+    status: Status,
+    /// The names of the entries read from the directory with `--delete`, or `None` if nothing is to
+    /// be deleted from its copy (including if reading it failed, and so they're incomplete)
+    names: Option<HashSet<OsString>>,
+}
+
+impl Frame {
+    fn new(directory: Directory, context: &Context) -> Self {
+        Frame {
+            entries: if directory.contents.is_some() {
+                Entries::Unread
+            } else {
+                Entries::Read
+            },
+            // Directories whose entries aren't copied (e.g. mount points) have nothing extraneous
+            names: (context.options.delete && directory.contents.is_some()).then(HashSet::new),
+            directory,
+            status: Status::Success,
+        }
+    }
+
+    /// Read the next batch of entries of the directory to copy, which is empty once all of them
+    /// have been read.
+    fn read(&mut self, context: &Context) -> Result<Vec<io::Result<DirEntry>>, Error> {
+        let mut entries = match mem::replace(&mut self.entries, Entries::Read) {
+            Entries::Unread => fs::read_dir(&self.directory.source)?,
+            Entries::Reading(entries) => entries,
+            Entries::Read => return Ok(Vec::new()),
+        };
+        // Reading the directory is held back until each batch of entries has been copied, so that
+        // no more than `max_inflight` entries (across every directory being copied) are held in
+        // memory at once, bar the one entry each directory is always allowed so as to make
+        // progress. Otherwise, it's closed once read, so that a deep tree doesn't hold each
+        // directory along the way open.
+        let batch: Vec<_> = match context.options.max_inflight {
+            Some(max_inflight) => {
+                let available =
+                    |inflight: usize| cmp::max(max_inflight.saturating_sub(inflight), 1);
+                let reserved = available(
+                    context
+                        .inflight
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |inflight| {
+                            Some(inflight + available(inflight))
+                        })
+                        .unwrap(),
+                );
+                let batch: Vec<_> = entries.by_ref().take(reserved).collect();
+                if batch.len() == reserved {
+                    self.entries = Entries::Reading(entries);
+                } else {
+                    context
+                        .inflight
+                        .fetch_sub(reserved - batch.len(), Ordering::Relaxed);
+                }
+                batch
+            }
+            None => entries.collect(),
+        };
+        if let Some(names) = &mut self.names {
+            if batch.iter().all(Result::is_ok) {
+                names.extend(batch.iter().flatten().map(DirEntry::file_name));
+            } else {
+                self.names = None;
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Finish copying the directory once every entry within it has been copied, returning the
+    /// status of the most severe error which occurred within it.
+    fn finish(self, context: &Context) -> Status {
+        let Directory {
+            source,
+            dest,
+            metadata,
+            set_mode,
+            contents,
+        } = self.directory;
+        let mut status = self.status;
+        // Deleted before the attributes of the directory are preserved, as deleting its entries
+        // updates its modification time
+        if let (Some(names), Some(location)) = (&self.names, &contents) {
+            status = cmp::max(
+                status,
+                delete_extraneous(&source, &dest, names, location, context),
+            );
+        }
+        let status = match finish_copy(
+            &source,
+            &dest,
+            metadata.as_ref(),
+            set_mode,
+            Kind::Directory,
+            context,
+        ) {
+            Ok(()) => status,
+            Err(err) => cmp::max(status, context.output.error(err)),
+        };
+        if let Some(progress) = &context.progress {
+            progress.add_file();
+        }
+        status
+    }
+}
+
+/// Copy every entry of `directory` (and everything within them) into it, and then finish copying
+/// the directory itself. Rather than recursing into each directory within it, directories are
+/// pushed onto an explicit stack, and each is finished once popped with every entry within it
+/// copied, so that the depth of the tree is bounded only by memory rather than the stack of the
+/// thread.
+fn copy_tree(directory: Directory, context: &Context) -> Status {
+    let mut stack = vec![Frame::new(directory, context)];
+    while let Some(frame) = stack.last_mut() {
+        match frame.read(context) {
+            Ok(batch) if !batch.is_empty() => {
+                let Directory {
+                    source,
+                    dest,
+                    contents,
+                    ..
+                } = &frame.directory;
+                let location = contents.clone().unwrap_or(Location::ROOT);
+                let len = batch.len();
+                let (status, directories) = copy_entries(batch, source, dest, location, context);
+                if context.options.max_inflight.is_some() {
+                    context.inflight.fetch_sub(len, Ordering::Relaxed);
+                }
+                frame.status = cmp::max(frame.status, status);
+                // Pushed in reverse, so that they're copied in order of name
+                stack.extend(
+                    directories
+                        .into_iter()
+                        .rev()
+                        .map(|directory| Frame::new(directory, context)),
+                );
+                continue;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                frame.status = cmp::max(frame.status, context.output.error(err));
+                frame.names = None;
+            }
+        }
+        let status = stack.pop().unwrap().finish(context);
+        match stack.last_mut() {
+            Some(parent) => parent.status = cmp::max(parent.status, status),
+            None => return status,
+        }
+    }
+    unreachable!("the stack is only emptied by returning")
+}
+
+/// The destination of each entry of the directory `dest` (whose entries are at `location`) by its
+/// name. With `--secure-dest`, `dest` is held open for as long as this is, so that each of them is
+/// reached through it (rather than by walking down to it from the root of the tree).
+fn entry_dest<'a>(
+    dest: &'a DestPath,
+    location: &Location,
+) -> Result<impl Fn(&OsStr) -> DestPath + Sync + 'a, Error> {
+    let directory = location
+        .dest_root
+        .as_ref()
+        .map(|root| root.open_within(dest))
+        .transpose()?;
+    Ok(move |name: &OsStr| match &directory {
+        Some(directory) => directory.entry(name),
+        None => DestPath::new(dest.join(name)),
+    })
+}
+
+/// Remove each entry of the directory `dest` (the copy of `source`, whose entries are at `location`)
+/// which isn't among `names`, the entries of `source`, returning the status of the most severe
+/// error which occurred. Entries which would be excluded from the copy are left as they are, as
+/// they would be were they in `source`, and in a dry run, entries are only reported as removed.
+fn delete_extraneous(
+    source: &Path,
+    dest: &DestPath,
+    names: &HashSet<OsString>,
+    location: &Location,
+    context: &Context,
+) -> Status {
+    let entries = match fs::read_dir(dest) {
+        Ok(entries) => entries,
+        // Nothing is created during a dry run, so there may well be no copy to delete from
+        Err(err) if context.options.dry_run && err.kind() == ErrorKind::NotFound => {
+            return Status::Success
+        }
+        Err(err) => return context.output.error(err),
+    };
+    let entry_dest = match entry_dest(dest, location) {
+        Ok(entry_dest) => entry_dest,
+        Err(err) => return context.output.error(err),
+    };
+    let root = location.root.as_deref().unwrap_or(source);
+    let mut status = Status::Success;
+    for entry in entries {
+        let name = match entry {
+            Ok(entry) => entry.file_name(),
+            Err(err) => {
+                status = cmp::max(status, context.output.error(Error::from(err)));
+                continue;
+            }
+        };
+        if names.contains(&name) || is_excluded(&source.join(&name), root, &context.options) {
+            continue;
+        }
+        let extraneous = entry_dest(&name);
+        let result = if context.options.dry_run {
+            Ok(())
+        } else {
+            fs::symlink_metadata(&extraneous).and_then(|metadata| {
+                if metadata.is_dir() {
+                    fs::remove_dir_all(&extraneous)
+                } else {
+                    fs::remove_file(&extraneous)
+                }
+            })
+        };
+        match result {
+            Ok(()) => context.output.deleted(&extraneous),
+            Err(err) => status = cmp::max(status, context.output.error(err)),
+        }
+    }
+    status
+}
+
+/// Copy `entries`, as read from the directory `source`, into the directory `dest`, returning the
+/// directories among them which are yet to be copied (see [`MAX_CONCURRENT_DEPTH`]).
+fn copy_entries(
+    mut entries: Vec<io::Result<DirEntry>>,
+    source: &Path,
+    dest: &DestPath,
+    location: Location,
+    context: &Context,
+) -> (Status, Vec<Directory>) {
+    // Excluded entries are never copied at all, such that excluded directories aren't descended into
+    if !context.options.exclude.is_empty() {
+        let root = location.root.as_deref().unwrap_or(source);
+        entries.retain(|entry| {
+            entry.as_ref().map_or(true, |entry| {
+                !is_excluded(&entry.path(), root, &context.options)
+            })
+        });
+    }
+    // Entries are copied in order of name, so that a single-threaded copy is deterministic
+    entries.sort_by_key(|entry| entry.as_ref().ok().map(|entry| entry.file_name()));
+    let entry_dest = match entry_dest(dest, &location) {
+        Ok(entry_dest) => entry_dest,
+        Err(err) => return (context.output.error(err), Vec::new()),
+    };
+    // Each entry is a task in the pool, which idle workers steal, so subdirectories are read (and
+    // their entries copied) concurrently with the rest of this directory. As `dest` was created
+    // before its entries were read, it exists before any of them are copied into it, and only the
+    // entries of the directories currently being copied are held in memory at once.
+    let concurrent = location.depth < MAX_CONCURRENT_DEPTH;
+    let copied: Vec<_> = entries
         .into_par_iter()
         .map(|entry| match entry {
-            Ok(entry) => copy_file(&entry.path(), &dest.join(entry.file_name())),
-            Err(err) => {
-                eprintln!("{}", err);
-                true
+            Ok(entry) => {
+                let dest = entry_dest(&entry.file_name());
+                // Retrieved relative to the directory, which is cheaper than by the full path (any
+                // error is reported when the path is stat'd instead)
+                let (source, metadata) = (entry.path(), entry.metadata().ok());
+                // Each entry holds the directory it was read from open, which isn't needed to copy
+                // it (and which every level of a deep tree would otherwise hold open at once)
+                drop(entry);
+                match start_copy(&source, metadata, &dest, location.clone(), context) {
+                    Started::Finished(status) => (status, None),
+                    Started::Directory(directory) if concurrent => {
+                        (copy_tree(*directory, context), None)
+                    }
+                    // Reached from the root once it's copied in turn, rather than holding the
+                    // directory containing it open until then
+                    Started::Directory(mut directory) => {
+                        directory.dest = directory.dest.detach();
+                        (Status::Success, Some(*directory))
+                    }
+                }
             }
+            Err(err) => (context.output.error(Error::from(err)), None),
         })
-        .reduce(|| false, BitOr::bitor))
+        .collect();
+    let status = copied
+        .iter()
+        .map(|(status, _)| *status)
+        .fold(Status::Success, cmp::max);
+    let directories = copied
+        .into_iter()
+        .filter_map(|(_, directory)| directory)
+        .collect();
+    (status, directories)
+}
+
+/// Whether `source` is given with a trailing separator and is a directory, in which case its
+/// contents are copied into the destination rather than the directory itself (as with rsync).
+fn is_contents(source: &Path) -> bool {
+    source.as_os_str().as_bytes().ends_with(b"/") && is_directory(source)
 }
 
-/// Copy each file in `sources` into the directory `dest`.
-fn copy_into(sources: &[PathBuf], dest: &Path) -> bool {
-    let metadata = fs::symlink_metadata(dest).unwrap_or_else(|err| fatal(err));
-    if !metadata.is_dir() {
-        fatal(format!("{} is not a directory", dest.display()));
+/// The path of `source` without any leading root or `.` components, under which it's copied into
+/// the destination with `options.parents`.
+fn parents_relative(source: &Path) -> Result<PathBuf, Error> {
+    let mut relative = PathBuf::new();
+    for component in source.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(Error::invalid_input(format!(
+                    "{}: cannot recreate a path containing '..' with '--parents'",
+                    source.display()
+                )))
+            }
+        }
+    }
+    Ok(relative)
+}
+
+/// Create each directory leading to the path of `source` within `dest` which doesn't already
+/// exist, returning that path and the directories created along with the modes to give them once
+/// the copy is made (so that read-only directories can still be copied into).
+fn create_parents(
+    source: &Path,
+    dest: &Path,
+    context: &Context,
+) -> Result<(PathBuf, Vec<(PathBuf, Permissions)>), Error> {
+    let relative = parents_relative(source)?;
+    let mut source_dir = PathBuf::from(if source.has_root() { "/" } else { "" });
+    let mut dest_dir = dest.to_path_buf();
+    let mut created = Vec::new();
+    for name in relative.parent().into_iter().flat_map(Path::iter) {
+        source_dir.push(name);
+        dest_dir.push(name);
+        if is_directory(&dest_dir) {
+            continue;
+        }
+        if !context.options.dry_run {
+            let mode = fs::metadata(&source_dir)?.permissions().mode();
+            // Writable by its owner until the copy within it is finished, as with any directory
+            match fs::create_dir(&dest_dir, mode | 0o700) {
+                // Created by the copy of another source sharing the directory
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && is_directory(&dest_dir) => {
+                    continue
+                }
+                result => result?,
+            }
+            let mode = match context.umask {
+                Some(umask) => mode & !umask,
+                None => mode,
+            };
+            created.push((dest_dir.clone(), Permissions::from_mode(mode)));
+        }
+        context
+            .output
+            .copied(Kind::Directory, &source_dir, &dest_dir, 0);
     }
+    Ok((dest.join(relative), created))
+}
+
+/// Copy `source` to its path within the directory `dest` (with `options.parents`), creating the
+/// directories leading to it first.
+fn copy_with_parents(source: &Path, dest: &Path, context: &Context) -> Status {
+    let (dest, created) = match create_parents(source, dest, context) {
+        Ok(parents) => parents,
+        Err(err) => return context.output.error(err),
+    };
+    let mut status = copy_file(source, &DestPath::new(dest), Location::ROOT, context);
+    for (dir, permissions) in created.into_iter().rev() {
+        if let Err(err) = fs::set_permissions(&dir, permissions) {
+            status = cmp::max(status, context.output.error(err));
+        }
+    }
+    status
+}
+
+/// Copy each file in `sources` into the directory `dest`, or the contents of each directory in
+/// `sources` given with a trailing separator.
+fn copy_into(sources: &[PathBuf], dest: &Path, context: &Context) -> Status {
     sources
         .into_par_iter()
         .map(|source| match source.file_name() {
-            Some(file_name) => copy_file(source, &dest.join(file_name)),
-            None => {
-                eprintln!("{}: invalid file path", source.display());
-                true
+            _ if context.options.parents => copy_with_parents(source, dest, context),
+            _ if is_contents(source) => {
+                copy_file(source, &DestPath::new(dest), Location::ROOT, context)
             }
+            Some(file_name) => copy_file(
+                source,
+                &DestPath::new(dest.join(file_name)),
+                Location::ROOT,
+                context,
+            ),
+            None => context.output.error(Error::new(format!(
+                "{}: invalid file path",
+                source.display()
+            ))),
         })
-        .reduce(|| false, BitOr::bitor)
+        .reduce(|| Status::Success, cmp::max)
+}
+
+/// The options governing a copy made with [`copy`].
+#[derive(Clone, Default)]
+pub struct CopyOptions {
+    pub options: Options,
+    /// Called with the number of bytes copied as each chunk of each file is copied
+    pub progress: Option<Arc<dyn Fn(u64) + Send + Sync>>,
+    /// Tracks the files being copied, to be removed should the copy be interrupted (see
+    /// [`Interrupt::watch`])
+    pub interrupt: Option<Arc<Interrupt>>,
 }
 
-pub fn fcp(args: &[String]) -> bool {
-    let args: Box<_> = args.iter().map(PathBuf::from).collect();
-    match args.as_ref() {
-        [] | [_] => fatal("Please provide at least two arguments (run 'fcp --help' for details)"),
-        [source, dest] => match fs::symlink_metadata(dest) {
-            Ok(metadata) if metadata.is_dir() => copy_into(array::from_ref(source), dest),
-            _ => copy_file(source, dest),
+/// The totals of a copy made with [`copy`].
+#[derive(Debug)]
+pub struct Summary {
+    /// The status of the most severe error which occurred
+    pub status: Status,
+    /// The number of regular files copied
+    pub files: u64,
+    /// The number of directories, symlinks, and special files (i.e. FIFOs and devices) copied
+    /// respectively
+    pub directories: u64,
+    pub symlinks: u64,
+    pub special: u64,
+    /// The number of bytes of contents copied (as counted while copying them)
+    pub bytes: u64,
+    /// How long the copy took
+    pub elapsed: Duration,
+    /// The number of existing destinations which weren't copied over
+    pub skipped: u64,
+    /// Of those skipped, the number whose contents were identical to those of their source (as
+    /// compared by `options.compare_content`)
+    pub identical: u64,
+    pub errors: u64,
+    /// The number of copies whose contents differed from those of their source (and which are
+    /// also counted among the errors)
+    pub mismatched: u64,
+    /// Every error which occurred, if they were collected by `options.keep_going` (or else none)
+    pub failures: Vec<Error>,
+}
+
+/// Copy `sources` to `dest`, either into it (if it's a directory, there are several sources, or
+/// `options.target_directory` is set) or else as the copy of the sole source. Each error which
+/// occurs while copying is reported (on stderr, unless the output format is machine-readable) as
+/// soon as it occurs, with only those preventing the copy from starting at all being returned.
+pub fn copy(sources: &[PathBuf], dest: &Path, options: CopyOptions) -> Result<Summary, Error> {
+    match options.options.jobs {
+        Some(jobs) => ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .map_err(|err| Error::new(format!("failed to start worker threads: {}", err)))?
+            .install(|| copy_in_pool(sources, dest, options)),
+        None => copy_in_pool(sources, dest, options),
+    }
+}
+
+// Every parallel operation within this uses the current thread pool
+fn copy_in_pool(sources: &[PathBuf], dest: &Path, options: CopyOptions) -> Result<Summary, Error> {
+    let CopyOptions {
+        options,
+        progress,
+        interrupt,
+    } = options;
+    let into = match sources {
+        [] => return Err(Error::invalid_input("no sources were given".to_string())),
+        [_] if options.no_target_directory => false,
+        _ if options.no_target_directory => {
+            return Err(Error::invalid_input(
+                "only one source may be given with '--no-target-directory'".to_string(),
+            ))
+        }
+        [_] if options.target_directory.is_none() => is_directory(dest),
+        _ => true,
+    };
+    if into && !matches!(fs::file_type(dest)?, FileType::Directory(_)) {
+        return Err(Error::new(format!(
+            "target '{}' is not a directory",
+            dest.display()
+        )));
+    }
+    if options.parents && !into {
+        return Err(Error::invalid_input(format!(
+            "with '--parents', the destination '{}' must be a directory",
+            dest.display()
+        )));
+    }
+    for source in sources {
+        let copy = match source.file_name() {
+            _ if !into => dest.to_path_buf(),
+            _ if options.parents => dest.join(parents_relative(source)?),
+            _ if is_contents(source) => dest.to_path_buf(),
+            Some(file_name) => dest.join(file_name),
+            // Reported as an invalid path once copying begins
+            None => continue,
+        };
+        ensure_distinct(source, &copy, &options)?;
+    }
+    // Opened up front, so that a log which can't be written to prevents the copy from starting
+    let log = options.log_file.as_ref().map(fs::append).transpose()?;
+    let context = Context::new(options, sources, progress, interrupt, log);
+    let reporter = context.progress.clone().map(Reporter::spawn);
+    let status = match sources {
+        [source] if !into => copy_file(source, &DestPath::new(dest), Location::ROOT, &context),
+        _ => copy_into(sources, dest, &context),
+    };
+    if let Some(reporter) = reporter {
+        reporter.finish();
+    }
+    Ok(context.output.finish(status))
+}
+
+/// The canonical form of `path`, which need not exist (in which case the nearest of its ancestors
+/// which does is canonicalized, and the rest of the path appended to that). This is only ever used
+/// to compare paths: copies are made to destinations as they're given, with any symlinks within
+/// them left for the kernel to follow, so symlinks are resolved here as they will be then.
+fn resolve(path: &Path) -> Result<PathBuf, Error> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    let resolved = loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            Err(err) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    rest.push(name);
+                    existing = if parent.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        parent
+                    };
+                }
+                _ => return Err(err),
+            },
+        }
+    };
+    Ok(rest
+        .iter()
+        .rev()
+        .fold(resolved, |path, name| path.join(name)))
+}
+
+/// Ensure that copying `source` to `dest` wouldn't copy a file over itself, or a directory into
+/// itself (which would never finish). Paths are compared once resolved, so that symlinks can't
+/// disguise either case.
+fn ensure_distinct(source: &Path, dest: &Path, options: &Options) -> Result<(), Error> {
+    let metadata = if options.dereference || options.dereference_arguments {
+        fs::metadata(source)
+    } else {
+        fs::symlink_metadata(source)
+    };
+    // Sources which don't exist fail to be copied with errors of their own
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    if let Ok(existing) = fs::metadata(dest) {
+        if (existing.dev(), existing.ino()) == (metadata.dev(), metadata.ino()) {
+            return Err(Error::new(format!(
+                "'{}' and '{}' are the same file",
+                source.display(),
+                dest.display()
+            )));
+        }
+    }
+    if metadata.is_dir() && resolve(dest)?.starts_with(resolve(source)?) {
+        return Err(Error::new(format!(
+            "cannot copy '{}' into itself, '{}'",
+            source.display(),
+            dest.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Write the contents of `source` (following symlinks) to stdout, which only regular files can be.
+fn copy_to_stdout(source: &Path, options: &Options) -> Result<(), Error> {
+    if !fs::metadata(source)?.is_file() {
+        return Err(Error::invalid_input(format!(
+            "{}: only regular files can be written to stdout",
+            source.display()
+        )));
+    }
+    fs::copy_to_stdout(source, options.buffer_size)?;
+    Ok(())
+}
+
+/// Read the paths listed in the file at `list` (or stdin, if it's `-`), each terminated by
+/// `separator`.
+fn read_sources(list: &Path, separator: u8) -> Result<Vec<PathBuf>, Error> {
+    let contents = if list == Path::new("-") {
+        let mut contents = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut contents)
+            .map_err(|err| Error::new(format!("failed to read stdin: {}", err)))?;
+        contents
+    } else {
+        fs::read(list)?
+    };
+    // Paths aren't necessarily valid UTF-8, so the list is split as bytes
+    Ok(contents
+        .split(|&byte| byte == separator)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+        .collect())
+}
+
+/// Expand each of `patterns` into the paths matching it, failing if any matches nothing at all.
+fn expand_globs(patterns: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let invalid = |reason: &dyn Display| {
+            Error::invalid_input(format!(
+                "invalid pattern '{}': {}",
+                pattern.display(),
+                reason
+            ))
+        };
+        let pattern = pattern
+            .to_str()
+            .ok_or_else(|| invalid(&"not valid UTF-8"))?;
+        let matched = paths.len();
+        for path in glob::glob(pattern).map_err(|err| invalid(&err))? {
+            paths.push(path.map_err(|err| Error::new(err.to_string()))?);
+        }
+        if paths.len() == matched {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no files match '{}'", pattern),
+            )));
+        }
+    }
+    Ok(paths)
+}
+
+/// Expand a leading `~` in `path` into the home directory of the current user (`$HOME`), or a
+/// leading `~user` into that of `user`, leaving any other path as it is.
+fn expand_tilde(path: &Path) -> Result<PathBuf, Error> {
+    let bytes = path.as_os_str().as_bytes();
+    let (first, rest) = match bytes.iter().position(|&byte| byte == b'/') {
+        Some(end) => bytes.split_at(end),
+        None => (bytes, &b""[..]),
+    };
+    let home = match first.strip_prefix(b"~") {
+        None => return Ok(path.to_path_buf()),
+        Some(b"") => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home),
+            None => {
+                unistd::User::from_uid(unistd::getuid())
+                    .ok()
+                    .flatten()
+                    .ok_or_else(|| {
+                        Error::new(format!(
+                            "{}: cannot determine the home directory",
+                            path.display()
+                        ))
+                    })?
+                    .dir
+            }
         },
-        [sources @ .., dest] => copy_into(sources, dest),
+        Some(name) => {
+            let user = str::from_utf8(name)
+                .ok()
+                .and_then(|name| unistd::User::from_name(name).ok().flatten());
+            match user {
+                Some(user) => user.dir,
+                None => {
+                    return Err(Error::invalid_input(format!(
+                        "{}: no such user '{}'",
+                        path.display(),
+                        String::from_utf8_lossy(name)
+                    )))
+                }
+            }
+        }
+    };
+    // Appended as bytes, so that a trailing separator (as in '~/') is kept
+    let mut expanded = home.into_os_string();
+    expanded.push(OsStr::from_bytes(rest));
+    Ok(PathBuf::from(expanded))
+}
+
+pub fn fcp(args: &[String]) -> Status {
+    let (mut options, mut args) =
+        options::parse(args).unwrap_or_else(|err| fatal(err, Status::Usage));
+    if options.expand_tilde {
+        for path in args.iter_mut().chain(options.target_directory.as_mut()) {
+            *path = expand_tilde(path).unwrap_or_else(|err| fatal(&err, (&err).into()));
+        }
+    }
+    let listing = options.files_from.is_some();
+    let (sources, dest) = match (&options.target_directory, args.as_ref()) {
+        (Some(_), []) if !listing => fatal(
+            "Please provide at least one source (run 'fcp --help' for details)",
+            Status::Usage,
+        ),
+        (Some(dest), sources) => (sources, dest.clone()),
+        (None, [dest]) if listing => (&[][..], dest.clone()),
+        (None, [] | [_]) => fatal(
+            "Please provide at least two arguments (run 'fcp --help' for details)",
+            Status::Usage,
+        ),
+        (None, [sources @ .., dest]) => (sources, dest.clone()),
+    };
+    if options.target_directory.is_none() && !listing && dest == Path::new("-") {
+        return match sources {
+            [source] => match copy_to_stdout(source, &options) {
+                Ok(()) => Status::Success,
+                Err(err) => fatal(&err, (&err).into()),
+            },
+            _ => fatal(
+                "Only a single SOURCE can be written to stdout",
+                Status::Usage,
+            ),
+        };
+    }
+    let mut sources = if options.glob {
+        expand_globs(sources).unwrap_or_else(|err| fatal(&err, (&err).into()))
+    } else {
+        sources.to_vec()
+    };
+    // Listed sources are always copied into the destination, as if it were given by `-t`
+    if let Some(list) = &options.files_from {
+        let separator = if options.null { b'\0' } else { b'\n' };
+        let listed = read_sources(list, separator).unwrap_or_else(|err| fatal(&err, (&err).into()));
+        sources.extend(listed);
+        options.target_directory = Some(dest.clone());
+    }
+    // Watched for before the copy spawns any threads, so that they all leave the signals to it
+    let interrupt = Arc::new(Interrupt::default());
+    if let Err(err) = interrupt.watch() {
+        if !options.quiet {
+            eprintln!("warning: {}", err);
+        }
+    }
+    let options = CopyOptions {
+        options,
+        progress: None,
+        interrupt: Some(interrupt),
+    };
+    match copy(&sources, &dest, options) {
+        Ok(summary) => summary.status,
+        Err(err) => fatal(&err, (&err).into()),
     }
 }
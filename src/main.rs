@@ -1,4 +1,4 @@
-use fcp::{fatal, fcp};
+use fcp::{fatal, fcp, Status};
 use std::env;
 use std::process;
 
@@ -6,16 +6,259 @@ static HELP: &str = "\
 fcp
 
 USAGE:
-    fcp SOURCE DESTINATION_FILE
+    fcp [OPTIONS] SOURCE DESTINATION_FILE
     Copy SOURCE to DESTINATION_FILE, overwriting DESTINATION_FILE if it exists
 
-    fcp SOURCE ... DESTINATION_DIRECTORY
-    Copy each SOURCE into DESTINATION_DIRECTORY";
+    fcp [OPTIONS] -T SOURCE DESTINATION
+    Copy SOURCE to DESTINATION, even if DESTINATION is an existing directory
+
+    fcp [OPTIONS] SOURCE ... DESTINATION_DIRECTORY
+    fcp [OPTIONS] -t DESTINATION_DIRECTORY SOURCE ...
+    Copy each SOURCE into DESTINATION_DIRECTORY
+
+    fcp [OPTIONS] --files-from=FILE [SOURCE ...] DESTINATION_DIRECTORY
+    Copy each SOURCE, and each source listed in FILE, into DESTINATION_DIRECTORY
+
+    fcp [OPTIONS] SOURCE -
+    Write the contents of the regular file SOURCE to stdout
+
+    Directories are merged into existing directories, whose modes are left as they are.
+    Copying over an existing file of a different type (a directory over a non-directory,
+    or vice versa) fails, unless -f is given, in which case the existing file is removed
+    first. With -n, files other than directories are never copied over anything at all.
+
+    A SOURCE directory given with a trailing '/' (e.g. 'src/') has its contents copied into
+    DESTINATION_DIRECTORY, rather than being copied into it itself (as with rsync).
+
+OPTIONS:
+    -0, --null                  Separate the sources listed by --files-from with NUL
+                                bytes (as with 'find -print0') rather than newlines
+    -a, --archive               Same as -P --preserve with every attribute preserved
+        --atomic                Copy each regular file to a temporary file alongside its
+                                destination, which is then renamed over it, so that the
+                                destination is never seen partially copied
+        --atime-preserve        Restore the access time of each SOURCE (and the files
+                                within it) once it has been read, where permitted
+        --attributes-only       Copy only the attributes being preserved (see --preserve)
+                                of each file to its existing destination, never its
+                                contents, failing for each destination which doesn't exist
+        --backup[=CONTROL]      Back up each existing destination file before
+                                overwriting it, where CONTROL may be any of:
+                                  simple    as FILE~
+                                  numbered  as FILE.~N~, one more than the latest such
+                                            backup
+                                  existing  numbered if FILE already has numbered
+                                            backups, simple otherwise (the default)
+                                  none      never make backups
+        --buffer-size=BYTES     Copy contents which can't be copied within the kernel
+                                BYTES at a time (optionally suffixed with K, M, or G),
+                                rather than at a size suited to each file
+        --bwlimit=RATE          Copy the contents of files at most RATE bytes per second
+                                in total (optionally suffixed with K, M, or G)
+        --checksum              Verify that each regular file copied has the same
+                                contents as its source, by reading both in full
+        --chmod=MODE            Change the mode of each file copied (other than symlinks)
+                                once it's created, after any mode preserved, by MODE: a
+                                comma-separated list of octal modes or symbolic changes as
+                                for chmod(1) (e.g. 'go-w,u+X'), in which no users given
+                                means 'a' regardless of the umask
+        --compare-content       Skip each regular file whose existing destination has the
+                                same contents, comparing their sizes before reading both
+        --copy-contents         Copy the data read from each FIFO (until its writer closes
+                                it) into a regular file, rather than creating a new FIFO
+        --copy-device-contents
+                                Copy the contents of each block device (all of it, where
+                                its size can be determined) into a regular file, as dd(1)
+                                would, even if device nodes are being preserved
+        --delete                Remove each file within the copy of a directory which isn't in
+                                the directory itself (once the rest of it has been copied),
+                                as with 'rsync --delete', other than those excluded (see
+                                --exclude); nothing is removed from the copy of a directory
+                                which couldn't be read in full
+    -L, --dereference           Copy the files which symlinks point to (including
+                                those given as a SOURCE), rather than the symlinks
+        --direct                Copy the contents of regular files which aren't sparse
+                                without going through the page cache (with O_DIRECT, on
+                                Linux filesystems supporting it), through a buffer of
+                                --buffer-size (rounded up to a multiple of 4 KiB)
+        --dry-run               Print the source and destination of each file which
+                                would be copied (as with --verbose), without copying
+                                anything
+        --exclude=PATTERN       Don't copy files within each SOURCE (or their contents)
+                                matching the glob PATTERN, which is matched against
+                                the path relative to SOURCE if it contains a '/', or else
+                                the name of the file; may be given more than once
+        --expand-tilde          Expand a leading '~' in each path given as an argument into
+                                the home directory of the current user ($HOME), or a
+                                leading '~USER' into that of USER, as a shell would
+        --fadvise               Advise the kernel that the contents of each regular file
+                                copied (and its copy) won't be needed again once copied,
+                                so that they don't crowd the page cache (Linux only)
+        --files-from=FILE       Copy the sources listed one per line in FILE (or stdin,
+                                if FILE is '-') in addition to each SOURCE
+    -f, --force                 Remove each existing destination which can't be copied
+                                over (e.g. as it's read-only or of a different type)
+                                and try again
+        --fsync                 Flush each file and directory copied to disk before
+                                moving on, so that the copy persists through a crash
+        --glob                  Expand each SOURCE as a glob pattern (e.g. 'src/*.log'),
+                                failing if any matches no files
+    -H                          Copy the files which symlinks given as a SOURCE point to,
+                                but copy symlinks within them as symlinks
+    -h, --help                  Print this help message
+        --include=PATTERN       Copy files matching PATTERN (as with --exclude) even if
+                                they're excluded; may be given more than once
+    -i, --interactive           Ask before overwriting each existing destination file
+                                (skipping it if stdin isn't a terminal)
+    -j, --jobs=N                Copy at most N files at once (by default, one per CPU);
+                                -j1 copies each file in turn
+        --keep-going            List every error which occurred once the copy is finished
+                                (errors never stop the rest of the copy from being made)
+    -l, --link                  Create each regular file as a hard link to its source rather
+                                than copying it, copying only those which can't be linked
+                                to (e.g. as they're on another filesystem)
+        --log-file=PATH         Append a line to PATH for each file copied, skipped, or
+                                failing to be copied, and for the summary of the totals,
+                                in the format of --output=json with the time of each (in
+                                seconds since the Unix epoch) added, regardless of --output
+        --max-depth=N           Copy only the files at most N levels below each SOURCE,
+                                creating the directories N levels below it empty (so
+                                that with N=0, SOURCE itself is created empty)
+        --max-inflight=N        Read the entries of directories in batches, copying each
+                                batch before reading the next, such that at most N are
+                                held in memory at once across every directory being
+                                copied (bar one for each, so that all make progress), at
+                                the cost of copying entries in name order only within
+                                batches
+        --mmap                  Read the contents of regular files which aren't sparse by
+                                mapping them into memory, rather than by copying them
+                                within the kernel or through a buffer
+    -n, --no-clobber            Skip each existing destination file rather than
+                                overwriting it (directories are still merged into)
+    -P, --no-dereference        Copy symlinks (including those given as a SOURCE) as
+                                symlinks, never following them (the default)
+        --no-ignore-special     Fail to copy sockets (and files of unknown types), rather
+                                than omitting them with a warning
+        --no-preserve=ATTR_LIST
+                                Don't preserve the comma-separated attributes in
+                                ATTR_LIST (as for --preserve), even if -a or --preserve
+                                is given after it; given 'mode', files are instead given
+                                the permissions a new file would have under the umask
+        --numeric-ids           Accepted for compatibility with rsync, as ownership is
+                                always preserved by numeric user and group IDs (without
+                                looking up their names on either side of the copy)
+    -x, --one-file-system       Don't descend into directories on filesystems other than
+                                that of their SOURCE (copying them as empty directories)
+        --output=FORMAT         Report operations on stdout as FORMAT, which is either
+                                'human' (the default) or 'json' (one JSON object per
+                                line for each file copied, skipped, or failing to be
+                                copied, followed by a summary of the totals)
+        --parallel-file-chunks=N
+                                Copy each regular file at least as large as the
+                                --parallel-file-threshold in N chunks at once (by
+                                default, as many as files are copied at once)
+        --parallel-file-threshold=BYTES
+                                Copy each regular file of at least BYTES (optionally
+                                suffixed with K, M, or G) in several chunks at once,
+                                rather than all at once (by default, none are)
+        --parents               Copy each SOURCE to its full path (without any leading '/')
+                                within DESTINATION_DIRECTORY, creating the directories
+                                leading to it (e.g. 'a/b/c' into 'dest' as 'dest/a/b/c')
+        --preallocate[=WHEN]    Allocate space for the contents of each regular file which
+                                isn't sparse before copying them, reducing fragmentation,
+                                for files of at least 1 MiB when WHEN is 'auto' (the
+                                default), always when WHEN is 'always', or never when
+                                WHEN is 'never'
+    -p                          Same as --preserve=timestamps
+        --preserve[=ATTR_LIST]  Preserve the comma-separated attributes in ATTR_LIST
+                                (default: timestamps), which may be any of:
+                                  mode        permissions (preserved unless --no-preserve=mode)
+                                  timestamps  access and modification times (and creation
+                                              times on macOS)
+                                  ownership   owner and group, by numeric ID (typically
+                                              requires root)
+                                  xattr       extended attributes
+                                  acl         POSIX ACLs (Linux only), which take precedence
+                                              over the group permissions of the copied mode
+                                  links       hard links between the files being copied
+                                  special     device nodes (typically requires root), which
+                                              are otherwise copied as regular files
+        --progress              Display the progress of the copy on stderr
+    -q, --quiet                 Don't print a summary of the files copied on stderr once
+                                the copy is finished, its progress, or warnings,
+                                leaving only errors; can't be combined with --verbose
+        --reflink[=WHEN]        Create copies of regular files as copy-on-write clones
+                                (sharing their data and holes, with FICLONE on Linux
+                                filesystems such as btrfs and XFS, or clonefile on macOS)
+                                when WHEN is 'auto' (the default) and cloning is
+                                possible, always when WHEN is 'always' (failing if
+                                cloning isn't possible), or never when WHEN is 'never'
+                                (in which case contents are only ever read and written,
+                                never copied within the kernel, as that may share them too)
+        --relative              Give the symlinks created by -s targets relative to the
+                                directories containing them, rather than absolute paths
+        --resume                Continue copying each regular file whose destination
+                                is no larger than it from the end of the destination,
+                                skipping those of the same size, if the destination has
+                                the same modification time as the source (as those
+                                left partially copied by a failed copy with --resume
+                                are given, and those completed with -p have), or its
+                                contents match the start of the source if --checksum
+                                is also given; any other destination is copied over
+                                from the start
+        --retries=N             Retry copying each file (other than a directory) up to N
+                                times should it fail with an error which may be transient
+                                (such as EIO or ETIMEDOUT, e.g. from a network filesystem),
+                                waiting 0.1 s before the first retry and twice as long
+                                before each one after that
+        --rewrite-symlinks=relative
+                                Rewrite the target of each symlink within a SOURCE which
+                                points within that SOURCE as the relative path to it, so
+                                that the copy points at the copy of the target (leaving
+                                the targets of others as they are)
+        --secure-dest           Reach the files within each directory copied through the
+                                copy of the directory, held open while they're copied and
+                                itself opened through the directory containing it without
+                                following symlinks (on Linux only), rather than by their
+                                paths, so that replacing a directory within the destination
+                                with a symlink while it's being copied (e.g. in a
+                                world-writable directory) can't redirect the copy elsewhere
+        --skip-existing         Skip each existing destination file as with -n, but
+                                print a line on stdout for each one skipped (even
+                                without --verbose)
+        --sparse=WHEN           Preserve holes in regular files which appear to contain
+                                them when WHEN is 'auto' (the default), always search
+                                for holes to preserve when WHEN is 'always', or never
+                                preserve holes when WHEN is 'never'
+    -s, --symbolic-link         Create each regular file as a symlink to the absolute path
+                                of its source rather than copying it
+        --symlink-target=HOW    Give each copy of a symlink the target of the symlink as
+                                it is when HOW is 'keep' (the default), converted into
+                                the path relative to the directory containing the copy
+                                when HOW is 'relative', or converted into an absolute
+                                path when HOW is 'absolute' (resolving '..' lexically)
+    -t, --target-directory=DIR  Copy each SOURCE into DIR
+    -T, --no-target-directory   Copy SOURCE to DESTINATION itself, merging it into
+                                DESTINATION if both are directories
+    -u, --update                Skip each regular file whose existing destination was
+                                modified at the same time as or after it
+    -v, --verbose               Print the source and destination of each file copied
+
+EXIT STATUS:
+    0   All files were copied successfully
+    1   An error occurred (and no more specific status applies)
+    2   The arguments were invalid
+    13  Permission was denied
+    18  A file couldn't be copied across filesystems
+    130 The copy was interrupted (by SIGINT or SIGTERM), in which case the files partially
+        copied are removed (unless --resume was given, in which case they're kept to be
+        resumed)
+    When several errors occur, the highest of their statuses is used.";
 
 fn main() {
-    let args: Box<_> = env::args().skip(1).collect();
+    let args: Box<[String]> = env::args().skip(1).collect();
     if args.iter().any(|arg| arg == "-h" || arg == "--help") {
-        fatal(HELP);
+        fatal(HELP, Status::Failure);
     }
     process::exit(fcp(&args) as i32);
 }
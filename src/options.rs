@@ -0,0 +1,787 @@
+//! Parsing of command-line arguments into the [`Options`] which govern how `fcp` copies files.
+
+use crate::filesystem::Error;
+use glob::Pattern;
+use std::path::PathBuf;
+
+/// The attributes of each source file which are carried over to its destination.
+#[derive(Clone, Copy, Debug)]
+pub struct Preserve {
+    /// The permissions of the source, which are otherwise those of a new file under the umask
+    pub mode: bool,
+    pub timestamps: bool,
+    pub ownership: bool,
+    pub xattr: bool,
+    pub acl: bool,
+    pub links: bool,
+    /// Device nodes, which are otherwise copied as regular files containing their contents
+    pub special: bool,
+}
+
+impl Preserve {
+    /// No attributes at all.
+    const NONE: Preserve = Preserve {
+        mode: false,
+        timestamps: false,
+        ownership: false,
+        xattr: false,
+        acl: false,
+        links: false,
+        special: false,
+    };
+
+    /// The attributes preserved by `--archive`.
+    pub const ARCHIVE: Preserve = Preserve {
+        mode: true,
+        timestamps: true,
+        ownership: true,
+        xattr: true,
+        acl: true,
+        links: true,
+        special: true,
+    };
+
+    /// Whether any attributes besides the mode (which symlinks don't have) are to be preserved.
+    pub fn any(&self) -> bool {
+        self.timestamps || self.ownership || self.xattr || self.acl
+    }
+
+    /// Add each attribute in `other` to the set being preserved.
+    fn include(&mut self, other: Preserve) {
+        self.mode |= other.mode;
+        self.timestamps |= other.timestamps;
+        self.ownership |= other.ownership;
+        self.xattr |= other.xattr;
+        self.acl |= other.acl;
+        self.links |= other.links;
+        self.special |= other.special;
+    }
+
+    /// Remove each attribute in `other` from the set being preserved.
+    fn exclude(&mut self, other: Preserve) {
+        self.mode &= !other.mode;
+        self.timestamps &= !other.timestamps;
+        self.ownership &= !other.ownership;
+        self.xattr &= !other.xattr;
+        self.acl &= !other.acl;
+        self.links &= !other.links;
+        self.special &= !other.special;
+    }
+
+    /// Parse the comma-separated `list` of attributes.
+    fn parse(list: &str) -> Result<Preserve, Error> {
+        let mut preserve = Preserve::NONE;
+        for attribute in list.split(',') {
+            match attribute {
+                "mode" => preserve.mode = true,
+                "timestamps" => preserve.timestamps = true,
+                "ownership" => preserve.ownership = true,
+                "xattr" => preserve.xattr = true,
+                "acl" => preserve.acl = true,
+                "links" => preserve.links = true,
+                "special" => preserve.special = true,
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid attribute '{}'",
+                        attribute
+                    )))
+                }
+            }
+        }
+        Ok(preserve)
+    }
+}
+
+impl Default for Preserve {
+    /// Only the mode is preserved by default.
+    fn default() -> Self {
+        Preserve {
+            mode: true,
+            ..Preserve::NONE
+        }
+    }
+}
+
+/// When to create copies of regular files as copy-on-write clones of their sources.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reflink {
+    /// Clone when possible, falling back to a normal copy otherwise
+    #[default]
+    Auto,
+    /// Clone, failing if this isn't possible
+    Always,
+    /// Always read and write the contents, never copying them within the kernel (which may share
+    /// them like a clone)
+    Never,
+}
+
+impl Reflink {
+    fn parse(when: &str) -> Result<Self, Error> {
+        match when {
+            "auto" => Ok(Reflink::Auto),
+            "always" => Ok(Reflink::Always),
+            "never" => Ok(Reflink::Never),
+            _ => Err(Error::invalid_input(format!(
+                "invalid argument '{}' for '--reflink'",
+                when
+            ))),
+        }
+    }
+}
+
+/// A change to the modes of the files copied, given by `--chmod` as a comma-separated list of
+/// clauses applied in turn, each either an octal mode or a symbolic change (as for chmod(1)).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chmod(Vec<Clause>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Clause {
+    Octal(u32),
+    Symbolic {
+        /// The bits of the mode affected, according to the users given (e.g. `go`)
+        who: u32,
+        operations: Vec<Operation>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Operation {
+    /// One of `+`, `-`, or `=`
+    operator: char,
+    bits: u32,
+    /// Whether execute permission is also affected if the file is a directory or is already
+    /// executable by anyone (`X`)
+    conditional_execute: bool,
+}
+
+impl Chmod {
+    fn parse(expression: &str) -> Result<Self, Error> {
+        expression
+            .split(',')
+            .map(|clause| {
+                Clause::parse(clause)
+                    .ok_or_else(|| Error::invalid_input(format!("invalid mode '{}'", expression)))
+            })
+            .collect::<Result<_, _>>()
+            .map(Chmod)
+    }
+
+    /// The mode resulting from applying this change to `mode`, that of a directory if `directory`
+    /// is set.
+    pub fn apply(&self, mode: u32, directory: bool) -> u32 {
+        self.0
+            .iter()
+            .fold(mode, |mode, clause| clause.apply(mode, directory))
+    }
+}
+
+impl Clause {
+    fn parse(clause: &str) -> Option<Self> {
+        if !clause.is_empty() && clause.bytes().all(|byte| (b'0'..=b'7').contains(&byte)) {
+            return u32::from_str_radix(clause, 8)
+                .ok()
+                .filter(|&mode| mode <= 0o7777)
+                .map(Clause::Octal);
+        }
+        let operations_start = clause.find(['+', '-', '='])?;
+        let who = match &clause[..operations_start] {
+            // As if the users were 'a', rather than respecting the umask as chmod(1) does
+            "" => 0o7777,
+            users => users.chars().try_fold(0, |who, user| {
+                Some(
+                    who | match user {
+                        'u' => 0o4700,
+                        'g' => 0o2070,
+                        'o' => 0o1007,
+                        'a' => 0o7777,
+                        _ => return None,
+                    },
+                )
+            })?,
+        };
+        let mut operations = Vec::new();
+        for c in clause[operations_start..].chars() {
+            match (c, operations.last_mut()) {
+                ('+' | '-' | '=', _) => operations.push(Operation {
+                    operator: c,
+                    bits: 0,
+                    conditional_execute: false,
+                }),
+                ('X', Some(operation)) => operation.conditional_execute = true,
+                (permission, Some(operation)) => {
+                    operation.bits |= match permission {
+                        'r' => 0o444,
+                        'w' => 0o222,
+                        'x' => 0o111,
+                        's' => 0o6000,
+                        't' => 0o1000,
+                        _ => return None,
+                    }
+                }
+                (_, None) => return None,
+            }
+        }
+        Some(Clause::Symbolic { who, operations })
+    }
+
+    fn apply(&self, mode: u32, directory: bool) -> u32 {
+        let (who, operations) = match self {
+            Clause::Octal(octal) => return (mode & !0o7777) | octal,
+            Clause::Symbolic { who, operations } => (who, operations),
+        };
+        operations.iter().fold(mode, |mode, operation| {
+            let mut bits = operation.bits;
+            if operation.conditional_execute && (directory || mode & 0o111 != 0) {
+                bits |= 0o111;
+            }
+            let bits = bits & who;
+            match operation.operator {
+                '+' => mode | bits,
+                '-' => mode & !bits,
+                _ => (mode & !who) | bits,
+            }
+        })
+    }
+}
+
+/// When to allocate space for the contents of regular files before copying them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preallocate {
+    /// Preallocate files large enough to benefit from it
+    #[default]
+    Auto,
+    /// Preallocate every file
+    Always,
+    /// Never preallocate
+    Never,
+}
+
+impl Preallocate {
+    fn parse(when: &str) -> Result<Self, Error> {
+        match when {
+            "auto" => Ok(Preallocate::Auto),
+            "always" => Ok(Preallocate::Always),
+            "never" => Ok(Preallocate::Never),
+            _ => Err(Error::invalid_input(format!(
+                "invalid argument '{}' for '--preallocate'",
+                when
+            ))),
+        }
+    }
+}
+
+/// How the targets of symlinks are transformed as they're copied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkTarget {
+    /// Keep each target as it is
+    #[default]
+    Keep,
+    /// Convert absolute targets into paths relative to the directory containing the copy
+    Relative,
+    /// Convert relative targets into absolute paths, resolved against the directory containing
+    /// the copy
+    Absolute,
+}
+
+impl SymlinkTarget {
+    fn parse(target: &str) -> Result<Self, Error> {
+        match target {
+            "keep" => Ok(SymlinkTarget::Keep),
+            "relative" => Ok(SymlinkTarget::Relative),
+            "absolute" => Ok(SymlinkTarget::Absolute),
+            _ => Err(Error::invalid_input(format!(
+                "invalid argument '{}' for '--symlink-target'",
+                target
+            ))),
+        }
+    }
+}
+
+/// How existing destinations are backed up before being overwritten, mirroring the version control
+/// methods of GNU coreutils.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backup {
+    /// Never make backups
+    #[default]
+    None,
+    /// Back up `dest` as `dest~`
+    Simple,
+    /// Back up `dest` as `dest.~N~`, where `N` is one more than that of the latest such backup
+    Numbered,
+    /// Make numbered backups of files which already have them, and simple backups otherwise
+    Existing,
+}
+
+impl Backup {
+    fn parse(control: &str) -> Result<Self, Error> {
+        match control {
+            "none" | "off" => Ok(Backup::None),
+            "simple" | "never" => Ok(Backup::Simple),
+            "numbered" | "t" => Ok(Backup::Numbered),
+            "existing" | "nil" => Ok(Backup::Existing),
+            _ => Err(Error::invalid_input(format!(
+                "invalid argument '{}' for '--backup'",
+                control
+            ))),
+        }
+    }
+}
+
+/// When to preserve holes in regular files, rather than filling them in with zeros.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sparse {
+    /// Preserve holes in files which appear to contain them
+    #[default]
+    Auto,
+    /// Always search for holes to preserve
+    Always,
+    /// Never preserve holes
+    Never,
+}
+
+impl Sparse {
+    fn parse(when: &str) -> Result<Self, Error> {
+        match when {
+            "auto" => Ok(Sparse::Auto),
+            "always" => Ok(Sparse::Always),
+            "never" => Ok(Sparse::Never),
+            _ => Err(Error::invalid_input(format!(
+                "invalid argument '{}' for '--sparse'",
+                when
+            ))),
+        }
+    }
+}
+
+/// The format of the reports of each operation written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Lines intended to be read by people (and only written with `--verbose`)
+    #[default]
+    Human,
+    /// One JSON object per line, for every operation
+    Json,
+}
+
+impl OutputFormat {
+    fn parse(format: &str) -> Result<Self, Error> {
+        match format {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(Error::invalid_input(format!(
+                "invalid argument '{}' for '--output'",
+                format
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub preserve: Preserve,
+    pub reflink: Reflink,
+    pub symlink_target: SymlinkTarget,
+    pub preallocate: Preallocate,
+    /// The change to make to the modes of the files copied once created, if any
+    pub chmod: Option<Chmod>,
+    pub sparse: Sparse,
+    pub backup: Backup,
+    pub progress: bool,
+    pub verbose: bool,
+    pub output: OutputFormat,
+    /// Ask before overwriting each existing destination
+    pub interactive: bool,
+    /// Never overwrite existing destinations
+    pub no_clobber: bool,
+    /// Skip existing destinations as with `no_clobber`, but report each one skipped
+    pub skip_existing: bool,
+    /// Create regular files as hard links to their sources where possible, rather than copying them
+    pub link: bool,
+    /// Create regular files as symlinks to their sources, rather than copying them
+    pub symbolic_link: bool,
+    /// Give the symlinks created by `symbolic_link` relative rather than absolute targets
+    pub relative: bool,
+    /// Remove existing destinations which can't be copied over
+    pub force: bool,
+    /// Copy the files symlinks point to rather than the symlinks themselves (whichever of `-L` and
+    /// `-P` is given last takes effect)
+    pub dereference: bool,
+    /// Copy the files which symlinks given as sources point to, but not those of symlinks within
+    /// them (whichever of `-H`, `-L`, and `-P` is given last takes effect)
+    pub dereference_arguments: bool,
+    /// Rewrite the targets of symlinks pointing within the tree being copied as relative paths
+    pub rewrite_symlinks: bool,
+    /// Fail to copy sockets and files of unknown types, rather than omitting them with a warning
+    pub no_ignore_special: bool,
+    /// Restore the access time of each source once it has been read
+    pub atime_preserve: bool,
+    /// Copy the data read from FIFOs into regular files, rather than creating new FIFOs
+    pub copy_contents: bool,
+    /// Copy the contents of block devices into regular files, even if devices are being preserved
+    pub copy_device_contents: bool,
+    /// Don't descend into directories on filesystems other than those of the sources
+    pub one_file_system: bool,
+    /// The directory to copy every positional argument into, if given by `--target-directory`
+    pub target_directory: Option<PathBuf>,
+    /// Treat the destination as the copy of the (sole) source even if it's an existing directory
+    pub no_target_directory: bool,
+    /// Copy each source to its full path (as given) within the target directory, creating the
+    /// directories leading to it
+    pub parents: bool,
+    /// Reach the files within each directory copied through the copy of the directory, held open,
+    /// rather than by their paths
+    pub secure_dest: bool,
+    /// The number of files to copy in parallel (by default, one per CPU)
+    pub jobs: Option<usize>,
+    /// The size of the buffer through which contents are copied in userspace (by default, one
+    /// suited to the size of each file)
+    pub buffer_size: Option<usize>,
+    /// The size from which regular files are copied in several chunks in parallel, if at all
+    pub parallel_file_threshold: Option<u64>,
+    /// The number of chunks to copy such files in (by default, one per worker thread)
+    pub parallel_file_chunks: Option<usize>,
+    /// Advise the kernel to drop the contents of regular files from the page cache once copied
+    pub fadvise: bool,
+    /// Copy the contents of regular files with `O_DIRECT`, bypassing the page cache
+    pub direct: bool,
+    /// Read the contents of regular files by mapping them into memory
+    pub mmap: bool,
+    /// The number of times to retry copying each file which fails with a transient error
+    pub retries: u32,
+    /// The limit on the rate at which contents are copied (in bytes per second), if any
+    pub bwlimit: Option<u64>,
+    /// Copy only the attributes of each file to its existing destination, never its contents
+    pub attributes_only: bool,
+    /// Skip regular files whose existing destinations have identical contents
+    pub compare_content: bool,
+    /// Verify that the contents of each regular file copied match those of its source
+    pub checksum: bool,
+    /// Copy each regular file to a temporary file which is then renamed over its destination
+    pub atomic: bool,
+    /// Flush each file and directory copied to the underlying storage device
+    pub fsync: bool,
+    /// Continue copying regular files from the end of their existing destinations left partially
+    /// copied (or whose contents are verified to match the start of the source with `checksum`)
+    pub resume: bool,
+    /// Expand each source given on the command line as a glob pattern
+    pub glob: bool,
+    /// Expand a leading `~` or `~user` in each path given on the command line into the home
+    /// directory of the current or given user
+    pub expand_tilde: bool,
+    /// A file listing sources to copy into the destination (or `-` for stdin), one per line
+    pub files_from: Option<PathBuf>,
+    /// Separate the sources listed by `files_from` with NUL bytes rather than newlines
+    pub null: bool,
+    /// Collect every error which occurs, to be summarized once the copy is finished
+    pub keep_going: bool,
+    /// A file to append a JSON line to for each operation performed, if any
+    pub log_file: Option<PathBuf>,
+    /// Don't print a summary of the totals of the copy once it's finished, its progress, or warnings
+    pub quiet: bool,
+    /// Patterns matching the files within each source which aren't to be copied
+    pub exclude: Vec<Pattern>,
+    /// Patterns matching files to copy even if they're excluded
+    pub include: Vec<Pattern>,
+    /// The number of directory entries to hold in memory at once (in total), if limited
+    pub max_inflight: Option<usize>,
+    /// The number of levels of directories below each source to copy the contents of, if limited
+    pub max_depth: Option<usize>,
+    /// Only copy regular files over existing destinations which are older than them
+    pub update: bool,
+    /// Report what would be copied without modifying the filesystem
+    pub dry_run: bool,
+    /// Remove the entries of each directory copied to which aren't in its source
+    pub delete: bool,
+}
+
+impl Options {
+    /// Set the options implied by `--archive`.
+    fn archive(&mut self) {
+        self.preserve.include(Preserve::ARCHIVE);
+        self.dereference = false;
+        self.dereference_arguments = false;
+    }
+
+    /// Set whether to follow symlinks at all (`-L`) or not (`-P`).
+    fn set_dereference(&mut self, dereference: bool) {
+        self.dereference = dereference;
+        self.dereference_arguments = false;
+    }
+
+    /// Set the option `name` (by its long name), which takes the argument `value`.
+    fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match name {
+            "bwlimit" => match parse_size(value) {
+                Some(rate) if rate > 0 => self.bwlimit = Some(rate as u64),
+                _ => return Err(Error::invalid_input(format!("invalid rate '{}'", value))),
+            },
+            "chmod" => self.chmod = Some(Chmod::parse(value)?),
+            "files-from" => self.files_from = Some(PathBuf::from(value)),
+            "log-file" => self.log_file = Some(PathBuf::from(value)),
+            "exclude" | "include" => {
+                let pattern = Pattern::new(value).map_err(|err| {
+                    Error::invalid_input(format!("invalid pattern '{}': {}", value, err))
+                })?;
+                if name == "exclude" {
+                    self.exclude.push(pattern);
+                } else {
+                    self.include.push(pattern);
+                }
+            }
+            "jobs" => match value.parse() {
+                Ok(jobs) if jobs > 0 => self.jobs = Some(jobs),
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid number of jobs '{}'",
+                        value
+                    )))
+                }
+            },
+            "buffer-size" => match parse_size(value) {
+                Some(size) if size > 0 => self.buffer_size = Some(size),
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid buffer size '{}'",
+                        value
+                    )))
+                }
+            },
+            "max-depth" => match value.parse() {
+                Ok(depth) => self.max_depth = Some(depth),
+                Err(_) => return Err(Error::invalid_input(format!("invalid depth '{}'", value))),
+            },
+            "max-inflight" => match value.parse() {
+                Ok(entries) if entries > 0 => self.max_inflight = Some(entries),
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid number of entries '{}'",
+                        value
+                    )))
+                }
+            },
+            "parallel-file-chunks" => match value.parse() {
+                Ok(chunks) if chunks > 0 => self.parallel_file_chunks = Some(chunks),
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid number of chunks '{}'",
+                        value
+                    )))
+                }
+            },
+            "parallel-file-threshold" => match parse_size(value) {
+                Some(size) if size > 0 => self.parallel_file_threshold = Some(size as u64),
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid file size '{}'",
+                        value
+                    )))
+                }
+            },
+            "retries" => match value.parse() {
+                Ok(retries) => self.retries = retries,
+                Err(_) => {
+                    return Err(Error::invalid_input(format!(
+                        "invalid number of retries '{}'",
+                        value
+                    )))
+                }
+            },
+            "target-directory" => self.target_directory = Some(PathBuf::from(value)),
+            _ => unreachable!("option '{}' doesn't take an argument", name),
+        }
+        Ok(())
+    }
+}
+
+/// Split `args` into the options they specify and the remaining positional arguments.
+pub fn parse(args: &[String]) -> Result<(Options, Box<[PathBuf]>), Error> {
+    let mut options = Options::default();
+    // Attributes excluded by --no-preserve, which are only removed once every option is parsed
+    // so that they're excluded even from a --preserve or -a given after them
+    let mut no_preserve = Preserve::NONE;
+    let mut paths = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            paths.extend(args.by_ref().map(PathBuf::from));
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            match (name, value) {
+                ("archive", None) => options.archive(),
+                ("dereference", None) => options.set_dereference(true),
+                ("no-dereference", None) => options.set_dereference(false),
+                ("no-ignore-special", None) => options.no_ignore_special = true,
+                ("atime-preserve", None) => options.atime_preserve = true,
+                ("attributes-only", None) => options.attributes_only = true,
+                ("copy-contents", None) => options.copy_contents = true,
+                ("copy-device-contents", None) => options.copy_device_contents = true,
+                ("delete", None) => options.delete = true,
+                ("dry-run", None) => options.dry_run = true,
+                ("checksum", None) => options.checksum = true,
+                ("compare-content", None) => options.compare_content = true,
+                ("resume", None) => options.resume = true,
+                ("atomic", None) => options.atomic = true,
+                ("fsync", None) => options.fsync = true,
+                ("mmap", None) => options.mmap = true,
+                ("direct", None) => options.direct = true,
+                ("fadvise", None) => options.fadvise = true,
+                ("glob", None) => options.glob = true,
+                ("expand-tilde", None) => options.expand_tilde = true,
+                ("force", None) => options.force = true,
+                ("interactive", None) => options.interactive = true,
+                ("link", None) => options.link = true,
+                ("symbolic-link", None) => options.symbolic_link = true,
+                ("relative", None) => options.relative = true,
+                ("no-clobber", None) => options.no_clobber = true,
+                // Ownership is only ever preserved by number, never by the names of the owner and
+                // group, so this is accepted merely for compatibility with rsync
+                ("numeric-ids", None) => {}
+                ("skip-existing", None) => options.skip_existing = true,
+                ("no-target-directory", None) => options.no_target_directory = true,
+                ("one-file-system", None) => options.one_file_system = true,
+                ("parents", None) => options.parents = true,
+                ("secure-dest", None) => options.secure_dest = true,
+                ("output", Some(format)) => options.output = OutputFormat::parse(format)?,
+                ("progress", None) => options.progress = true,
+                ("update", None) => options.update = true,
+                ("verbose", None) => options.verbose = true,
+                ("null", None) => options.null = true,
+                ("keep-going", None) => options.keep_going = true,
+                ("quiet", None) => options.quiet = true,
+                ("preserve", None) => options.preserve.timestamps = true,
+                ("preserve", Some(list)) => options.preserve.include(Preserve::parse(list)?),
+                ("no-preserve", Some(list)) => no_preserve.include(Preserve::parse(list)?),
+                ("rewrite-symlinks", Some("relative")) => options.rewrite_symlinks = true,
+                ("preallocate", None) => options.preallocate = Preallocate::Always,
+                ("preallocate", Some(when)) => options.preallocate = Preallocate::parse(when)?,
+                ("reflink", None) => options.reflink = Reflink::Always,
+                ("reflink", Some(when)) => options.reflink = Reflink::parse(when)?,
+                ("backup", None) => options.backup = Backup::Existing,
+                ("backup", Some(control)) => options.backup = Backup::parse(control)?,
+                ("sparse", Some(when)) => options.sparse = Sparse::parse(when)?,
+                ("symlink-target", Some(target)) => {
+                    options.symlink_target = SymlinkTarget::parse(target)?
+                }
+                (
+                    "buffer-size"
+                    | "bwlimit"
+                    | "chmod"
+                    | "exclude"
+                    | "files-from"
+                    | "include"
+                    | "jobs"
+                    | "log-file"
+                    | "max-depth"
+                    | "max-inflight"
+                    | "parallel-file-chunks"
+                    | "parallel-file-threshold"
+                    | "retries"
+                    | "target-directory",
+                    _,
+                ) => {
+                    let value = value
+                        .or_else(|| args.next().map(String::as_str))
+                        .ok_or_else(|| {
+                            Error::invalid_input(format!("option '{}' requires an argument", arg))
+                        })?;
+                    options.set(name, value)?;
+                }
+                _ => {
+                    return Err(Error::invalid_input(format!(
+                        "unrecognized option '{}'",
+                        arg
+                    )))
+                }
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            for (i, flag) in arg.char_indices().skip(1) {
+                match flag {
+                    // The remainder of the argument (or else the next argument) is the value
+                    'j' | 't' => {
+                        let rest = &arg[i + flag.len_utf8()..];
+                        let value = if rest.is_empty() {
+                            args.next().ok_or_else(|| {
+                                Error::invalid_input(format!(
+                                    "option requires an argument -- '{}'",
+                                    flag
+                                ))
+                            })?
+                        } else {
+                            rest
+                        };
+                        let name = if flag == 'j' {
+                            "jobs"
+                        } else {
+                            "target-directory"
+                        };
+                        options.set(name, value)?;
+                        break;
+                    }
+                    '0' => options.null = true,
+                    'a' => options.archive(),
+                    'f' => options.force = true,
+                    'i' => options.interactive = true,
+                    'H' => {
+                        options.dereference = false;
+                        options.dereference_arguments = true;
+                    }
+                    'l' => options.link = true,
+                    'L' => options.set_dereference(true),
+                    'n' => options.no_clobber = true,
+                    'p' => options.preserve.timestamps = true,
+                    'q' => options.quiet = true,
+                    's' => options.symbolic_link = true,
+                    'P' => options.set_dereference(false),
+                    'T' => options.no_target_directory = true,
+                    'u' => options.update = true,
+                    'v' => options.verbose = true,
+                    'x' => options.one_file_system = true,
+                    _ => {
+                        return Err(Error::invalid_input(format!(
+                            "invalid option -- '{}'",
+                            flag
+                        )))
+                    }
+                }
+            }
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    options.preserve.exclude(no_preserve);
+    #[cfg(not(target_os = "linux"))]
+    if options.secure_dest {
+        return Err(Error::invalid_input(
+            "option '--secure-dest' is only supported on Linux".to_string(),
+        ));
+    }
+    if options.force && options.no_clobber {
+        return Err(Error::invalid_input(
+            "options '--force' and '--no-clobber' are mutually exclusive".to_string(),
+        ));
+    }
+    if options.quiet && options.verbose {
+        return Err(Error::invalid_input(
+            "options '--quiet' and '--verbose' are mutually exclusive".to_string(),
+        ));
+    }
+    if options.target_directory.is_some() && options.no_target_directory {
+        return Err(Error::invalid_input(
+            "options '--target-directory' and '--no-target-directory' are mutually exclusive"
+                .to_string(),
+        ));
+    }
+    Ok((options, paths.into_boxed_slice()))
+}
+
+// Parse a number of bytes, optionally suffixed with K, M or G (as powers of 1024)
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, shift) = match size.strip_suffix(['K', 'M', 'G']) {
+        Some(digits) => (digits, 10 * (1 + "KMG".find(&size[digits.len()..])?)),
+        None => (size, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
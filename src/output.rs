@@ -0,0 +1,357 @@
+//! Reporting of the operations performed over the course of a copy.
+
+use crate::filesystem::{Error, ErrorKind, FileType};
+use crate::options::OutputFormat;
+use crate::progress::format_bytes;
+use crate::{Status, Summary};
+use std::fmt::{Display, Write as _};
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The kind of a file copied, as totalled in the summary of the copy.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Regular,
+    Directory,
+    Symlink,
+    /// FIFOs, devices, and sockets
+    Special,
+}
+
+impl From<&FileType> for Kind {
+    fn from(file_type: &FileType) -> Self {
+        match file_type {
+            FileType::Regular(_) => Kind::Regular,
+            FileType::Directory(_) => Kind::Directory,
+            FileType::Symlink(_) => Kind::Symlink,
+            _ => Kind::Special,
+        }
+    }
+}
+
+/// The sole writer of reports to stdout. As files are copied in parallel, each report is written
+/// while holding a lock so that reports are never interleaved with one another.
+pub struct Output {
+    verbose: bool,
+    format: OutputFormat,
+    stdout: Mutex<io::Stdout>,
+    /// Suppress the summary at the end of the copy and warnings
+    quiet: bool,
+    start: Instant,
+    /// Totals for the summary reported at the end of the copy
+    files: AtomicU64,
+    directories: AtomicU64,
+    symlinks: AtomicU64,
+    special: AtomicU64,
+    bytes: AtomicU64,
+    skipped: AtomicU64,
+    /// Of those skipped, the number whose contents were identical to those of their source
+    identical: AtomicU64,
+    errors: AtomicU64,
+    mismatched: AtomicU64,
+    /// Every error reported, if they're being collected
+    failures: Option<Mutex<Vec<Error>>>,
+    /// The file to which every report is also appended as a timestamped JSON line, if any
+    log: Option<Mutex<File>>,
+}
+
+impl Output {
+    /// Create the output for a copy, collecting the errors reported for the summary if `keep_going`
+    /// is set, printing the totals of the copy once it's finished and warnings unless `quiet` is,
+    /// and logging every report to `log` if given.
+    pub fn new(
+        verbose: bool,
+        format: OutputFormat,
+        keep_going: bool,
+        quiet: bool,
+        log: Option<File>,
+    ) -> Self {
+        Output {
+            verbose,
+            format,
+            stdout: Mutex::new(io::stdout()),
+            quiet,
+            start: Instant::now(),
+            files: AtomicU64::new(0),
+            directories: AtomicU64::new(0),
+            symlinks: AtomicU64::new(0),
+            special: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            identical: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            mismatched: AtomicU64::new(0),
+            failures: if keep_going {
+                Some(Mutex::new(Vec::new()))
+            } else {
+                None
+            },
+            log: log.map(Mutex::new),
+        }
+    }
+
+    fn report(&self, line: impl FnOnce(&mut io::Stdout) -> io::Result<()>) {
+        let mut stdout = self.stdout.lock().unwrap();
+        // Failing to report an operation (e.g. because stdout was closed) is no reason to abort it
+        let _ = line(&mut stdout);
+    }
+
+    /// Report an operation described by the JSON object `json`, printing it on stdout if the
+    /// output is machine-readable, and logging it (with the time it occurred) if it's being logged.
+    fn report_json(&self, json: impl FnOnce() -> String) {
+        if self.format != OutputFormat::Json && self.log.is_none() {
+            return;
+        }
+        let json = json();
+        if self.format == OutputFormat::Json {
+            self.report(|stdout| writeln!(stdout, "{}", json));
+        }
+        if let Some(log) = &self.log {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            // The log was opened successfully, so failing to write to it is ignored like failing
+            // to write to stdout
+            let _ = writeln!(
+                log.lock().unwrap(),
+                r#"{{"time":{:.3},{}"#,
+                time.as_secs_f64(),
+                &json[1..]
+            );
+        }
+    }
+
+    /// Account for `bytes` of contents having been copied.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Report that `source` (a file of kind `kind`) was copied to `dest`, with `bytes` being the
+    /// size of its contents.
+    pub fn copied(&self, kind: Kind, source: &Path, dest: &Path, bytes: u64) {
+        let count = match kind {
+            Kind::Regular => &self.files,
+            Kind::Directory => &self.directories,
+            Kind::Symlink => &self.symlinks,
+            Kind::Special => &self.special,
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        if self.format == OutputFormat::Human && self.verbose {
+            self.report(|stdout| {
+                writeln!(stdout, "'{}' -> '{}'", source.display(), dest.display())
+            });
+        }
+        self.report_json(|| {
+            format!(
+                r#"{{"op":"copy","src":{},"dst":{},"bytes":{}}}"#,
+                json_path(source),
+                json_path(dest),
+                bytes
+            )
+        });
+    }
+
+    /// Report that copying to the existing file `dest` was skipped.
+    pub fn skipped(&self, dest: &Path) {
+        self.skip(dest, None);
+    }
+
+    /// Report that copying to `dest` was skipped because it exists, even if the output isn't
+    /// verbose.
+    pub fn skipped_existing(&self, dest: &Path) {
+        self.skip(dest, Some("exists"));
+    }
+
+    /// Report that copying to `dest` was skipped because its contents are identical to those of
+    /// its source.
+    pub fn skipped_identical(&self, dest: &Path) {
+        self.identical.fetch_add(1, Ordering::Relaxed);
+        self.skip(dest, Some("identical").filter(|_| self.verbose));
+    }
+
+    fn skip(&self, dest: &Path, reason: Option<&str>) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        if self.format == OutputFormat::Human {
+            match reason {
+                Some(reason) => self
+                    .report(|stdout| writeln!(stdout, "skipped '{}' ({})", dest.display(), reason)),
+                None if self.verbose => {
+                    self.report(|stdout| writeln!(stdout, "skipped '{}'", dest.display()))
+                }
+                None => {}
+            }
+        }
+        self.report_json(|| format!(r#"{{"op":"skip","dst":{}}}"#, json_path(dest)));
+    }
+
+    /// Report that the extraneous file `dest` was removed (or would have been, in a dry run).
+    pub fn deleted(&self, dest: &Path) {
+        if self.format == OutputFormat::Human && self.verbose {
+            self.report(|stdout| writeln!(stdout, "removed '{}'", dest.display()));
+        }
+        self.report_json(|| format!(r#"{{"op":"delete","dst":{}}}"#, json_path(dest)));
+    }
+
+    /// Warn of a problem which doesn't prevent the copy from proceeding, unless the output is quiet.
+    pub fn warning(&self, message: impl Display) {
+        if !self.quiet {
+            eprintln!("warning: {}", message);
+        }
+    }
+
+    /// Report `err`, which prevented a file from being copied, returning the status it warrants.
+    /// Errors are written to stderr unless the output is machine-readable.
+    pub fn error(&self, err: Error) -> Status {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if err.kind() == ErrorKind::ChecksumMismatch {
+            self.mismatched.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.format == OutputFormat::Human {
+            eprintln!("{}", err);
+        }
+        self.report_json(|| {
+            let source = err.path().map_or_else(|| "null".to_string(), json_path);
+            format!(
+                r#"{{"op":"error","src":{},"message":{},"kind":"{}"}}"#,
+                source,
+                json_string(&err.to_string()),
+                kind_name(err.kind())
+            )
+        });
+        let status = Status::from(&err);
+        if let Some(failures) = &self.failures {
+            failures.lock().unwrap().push(err);
+        }
+        status
+    }
+
+    /// Total up the operations reported, with `status` being that of the copy as a whole. The
+    /// totals are reported if the output is machine-readable, and otherwise any copies which
+    /// failed to be verified and every error collected are, followed by a line summarizing the
+    /// totals (unless the output is quiet).
+    pub fn finish(mut self, status: Status) -> Summary {
+        let summary = Summary {
+            status,
+            files: self.files.load(Ordering::Relaxed),
+            directories: self.directories.load(Ordering::Relaxed),
+            symlinks: self.symlinks.load(Ordering::Relaxed),
+            special: self.special.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            elapsed: self.start.elapsed(),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            identical: self.identical.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            mismatched: self.mismatched.load(Ordering::Relaxed),
+            failures: self
+                .failures
+                .take()
+                .map_or_else(Vec::new, |failures| failures.into_inner().unwrap()),
+        };
+        if self.format == OutputFormat::Human && summary.mismatched > 0 {
+            eprintln!(
+                "{} of the files copied failed to be verified",
+                summary.mismatched
+            );
+        }
+        if self.format == OutputFormat::Human && !summary.failures.is_empty() {
+            match summary.failures.len() {
+                1 => eprintln!("\n1 error occurred:"),
+                errors => eprintln!("\n{} errors occurred:", errors),
+            }
+            for err in &summary.failures {
+                eprintln!("    {}", err);
+            }
+        }
+        if self.format == OutputFormat::Human && !self.quiet {
+            let mut copied = count(summary.files, "file", "files");
+            for (total, singular, plural) in [
+                (summary.directories, "directory", "directories"),
+                (summary.symlinks, "symlink", "symlinks"),
+                (summary.special, "special file", "special files"),
+            ] {
+                if total > 0 {
+                    copied.push_str(", ");
+                    copied.push_str(&count(total, singular, plural));
+                }
+            }
+            let skipped = match (summary.skipped, summary.identical) {
+                (0, _) => String::new(),
+                (skipped, 0) => format!(", skipping {}", skipped),
+                (skipped, identical) => format!(", skipping {} ({} identical)", skipped, identical),
+            };
+            eprintln!(
+                "copied {} ({}) in {:.1} s{}",
+                copied,
+                format_bytes(summary.bytes),
+                summary.elapsed.as_secs_f64(),
+                skipped
+            );
+        }
+        self.report_json(|| {
+            format!(
+                concat!(
+                    r#"{{"op":"summary","files":{},"directories":{},"symlinks":{},"#,
+                    r#""special":{},"bytes":{},"skipped":{},"identical":{},"errors":{},"#,
+                    r#""seconds":{:.3}}}"#
+                ),
+                summary.files,
+                summary.directories,
+                summary.symlinks,
+                summary.special,
+                summary.bytes,
+                summary.skipped,
+                summary.identical,
+                summary.errors,
+                summary.elapsed.as_secs_f64()
+            )
+        });
+        summary
+    }
+}
+
+/// Format `total` followed by the noun it counts, e.g. `1 file` or `2 files`.
+fn count(total: u64, singular: &str, plural: &str) -> String {
+    format!("{} {}", total, if total == 1 { singular } else { plural })
+}
+
+fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::PermissionDenied => "permission_denied",
+        ErrorKind::AlreadyExists => "already_exists",
+        ErrorKind::CrossDevice => "cross_device",
+        ErrorKind::InvalidInput => "invalid_input",
+        ErrorKind::ChecksumMismatch => "checksum_mismatch",
+        ErrorKind::Io => "io",
+        ErrorKind::Other => "other",
+    }
+}
+
+// Paths which aren't valid UTF-8 are reported lossily, as JSON strings can't represent them
+fn json_path(path: &Path) -> String {
+    json_string(&path.to_string_lossy())
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,159 @@
+//! Live reporting of how far along a copy is.
+
+use crate::filesystem as fs;
+use nix::{libc, unistd};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Counts of the files and bytes copied so far, alongside the totals to be copied.
+pub struct Progress {
+    total_files: u64,
+    total_bytes: u64,
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+// Count the files (of every type, including directories) and bytes (of regular files) in the tree
+// rooted at `path`. Errors are ignored, as they'll be reported when the copy itself encounters them.
+fn tally(path: &Path) -> (u64, u64) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return (1, 0),
+    };
+    if !metadata.is_dir() {
+        return (
+            1,
+            if metadata.is_file() {
+                metadata.len()
+            } else {
+                0
+            },
+        );
+    }
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(Result::ok).collect::<Box<_>>(),
+        Err(_) => return (1, 0),
+    };
+    let (files, bytes) = entries
+        .into_par_iter()
+        .map(|entry| tally(&entry.path()))
+        .reduce(|| (0, 0), |(a, b), (c, d)| (a + c, b + d));
+    (files + 1, bytes)
+}
+
+impl Progress {
+    /// Walk each of `sources` to determine the totals to be copied.
+    pub fn new(sources: &[PathBuf]) -> Self {
+        let (total_files, total_bytes) = sources
+            .into_par_iter()
+            .map(|source| tally(source))
+            .reduce(|| (0, 0), |(a, b), (c, d)| (a + c, b + d));
+        Progress {
+            total_files,
+            total_bytes,
+            files: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add_file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn render(&self, elapsed: Duration, bar: bool) -> String {
+        let files = self.files.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let throughput = (bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)) as u64;
+        let status = format!(
+            "{}/{} files, {}/{} ({}/s)",
+            files,
+            self.total_files,
+            format_bytes(bytes),
+            format_bytes(self.total_bytes),
+            format_bytes(throughput)
+        );
+        if !bar {
+            return status;
+        }
+        const WIDTH: u64 = 30;
+        let fraction = if self.total_bytes > 0 {
+            bytes as f64 / self.total_bytes as f64
+        } else if self.total_files > 0 {
+            files as f64 / self.total_files as f64
+        } else {
+            1.0
+        };
+        let filled = ((fraction.min(1.0) * WIDTH as f64) as u64).min(WIDTH) as usize;
+        format!(
+            "[{}{}] {:>3}% {}",
+            "#".repeat(filled),
+            " ".repeat(WIDTH as usize - filled),
+            (fraction.min(1.0) * 100.0) as u64,
+            status
+        )
+    }
+}
+
+/// Format `bytes` as a human-readable quantity using binary prefixes (e.g. `1.5 GiB`).
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// A background thread which periodically writes the state of a [`Progress`] to stderr. When
+/// stderr is a terminal this takes the form of a continuously updated progress bar, while
+/// otherwise a line describing the progress made is written every few seconds.
+pub struct Reporter {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Reporter {
+    pub fn spawn(progress: Arc<Progress>) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let is_terminal = unistd::isatty(libc::STDERR_FILENO).unwrap_or(false);
+        let interval = Duration::from_millis(if is_terminal { 100 } else { 5000 });
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            let report = |last| {
+                let status = progress.render(start.elapsed(), is_terminal);
+                let mut stderr = io::stderr();
+                let _ = if is_terminal {
+                    write!(stderr, "\r\x1b[K{}{}", status, if last { "\n" } else { "" })
+                } else {
+                    writeln!(stderr, "{}", status)
+                };
+            };
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                report(false);
+            }
+            report(true);
+        });
+        Reporter { stop, thread }
+    }
+
+    /// Stop reporting, writing the final state of the progress made.
+    pub fn finish(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
@@ -0,0 +1,44 @@
+//! Asking the user whether to proceed with individual operations during an interactive copy.
+
+use nix::{libc, unistd};
+use std::fs::File;
+use std::io::{prelude::*, BufReader};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The controlling terminal, from which answers to prompts are read. As files are copied in
+/// parallel, it is locked for the duration of each prompt so that prompts are never interleaved
+/// with one another (or with their answers).
+pub struct Prompt {
+    tty: Option<Mutex<BufReader<File>>>,
+}
+
+impl Prompt {
+    /// Open the controlling terminal, unless stdin isn't a terminal (in which case every prompt is
+    /// answered negatively without being asked).
+    pub fn new() -> Self {
+        let tty = if unistd::isatty(libc::STDIN_FILENO).unwrap_or(false) {
+            File::open("/dev/tty").ok()
+        } else {
+            None
+        };
+        Prompt {
+            tty: tty.map(|tty| Mutex::new(BufReader::new(tty))),
+        }
+    }
+
+    /// Ask the user whether the existing file `dest` should be overwritten.
+    pub fn overwrite(&self, dest: &Path) -> bool {
+        let tty = match &self.tty {
+            Some(tty) => tty,
+            None => return false,
+        };
+        let mut tty = tty.lock().unwrap();
+        eprint!("overwrite '{}'? (y/n) ", dest.display());
+        let mut answer = String::new();
+        match tty.read_line(&mut answer) {
+            Ok(_) => answer.trim_start().starts_with(['y', 'Y']),
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Rewriting of the targets of symlinks as they're copied.
+//!
+//! Paths are resolved lexically, without consulting the filesystem, so a `..` component following
+//! a symlink to a directory is taken to return to the directory containing that symlink rather
+//! than the parent of its target.
+
+use crate::filesystem::Error;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// `path` with each `.` component removed, and each `..` component removed along with the
+/// component preceding it (where there is one to remove).
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // The parent of the root directory is the root directory itself
+                Some(Component::RootDir) => {}
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The normalized absolute path of `path`, relative to the current directory if it's relative.
+fn absolute(path: &Path) -> Result<PathBuf, Error> {
+    if path.is_absolute() {
+        Ok(normalize(path))
+    } else {
+        Ok(normalize(&env::current_dir()?.join(path)))
+    }
+}
+
+/// The path of `path` relative to the directory `base`, both of which are normalized and absolute.
+fn relative(path: &Path, base: &Path) -> PathBuf {
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(path, base)| path == base)
+        .count();
+    let mut relative: PathBuf = base.components().skip(common).map(|_| "..").collect();
+    relative.extend(path.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+/// The directory containing the symlink `link`.
+fn parent(link: &Path) -> Result<PathBuf, Error> {
+    absolute(link.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// The target to give the copy of the symlink `source` pointing at `target`, where `source` is
+/// within the tree rooted at `root`. If `target` lies within the tree, this is the relative path
+/// to it from `source` (which at the destination points at the copy of `target`); otherwise, it's
+/// `target` as it is.
+pub fn within_tree(source: &Path, target: &Path, root: &Path) -> Result<PathBuf, Error> {
+    let directory = parent(source)?;
+    let resolved = normalize(&directory.join(target));
+    Ok(if resolved.starts_with(absolute(root)?) {
+        relative(&resolved, &directory)
+    } else {
+        target.to_path_buf()
+    })
+}
+
+/// `target` as a path relative to the directory containing the symlink `dest` if it's absolute,
+/// or else as it is.
+pub fn to_relative(dest: &Path, target: &Path) -> Result<PathBuf, Error> {
+    Ok(if target.is_absolute() {
+        relative(&normalize(target), &parent(dest)?)
+    } else {
+        target.to_path_buf()
+    })
+}
+
+/// `target` as an absolute path, resolved against the directory containing the symlink `dest` if
+/// it's relative.
+pub fn to_absolute(dest: &Path, target: &Path) -> Result<PathBuf, Error> {
+    Ok(normalize(&parent(dest)?.join(target)))
+}
+
+/// The target to give the symlink `dest` created in place of a copy of `source`: the absolute path
+/// of `source`, or that path relative to the directory containing `dest` if `relative` is set.
+pub fn to_source(source: &Path, dest: &Path, relative: bool) -> Result<PathBuf, Error> {
+    let target = absolute(source).map_err(|err| {
+        Error::new(format!(
+            "{}: cannot make the path absolute: {}",
+            source.display(),
+            err
+        ))
+    })?;
+    if relative {
+        to_relative(dest, &target)
+    } else {
+        Ok(target)
+    }
+}
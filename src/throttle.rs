@@ -0,0 +1,56 @@
+//! Limiting the rate at which the contents of files are copied.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A token bucket shared by every thread copying files, such that the limit applies to the copy
+/// as a whole. Each byte copied spends a token, with tokens accruing at the limited rate up to a
+/// second's worth; a thread which spends more tokens than remain sleeps until they've accrued.
+pub struct Limiter {
+    /// In bytes per second
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Negative once overspent, at which point each thread must wait for the debt to be repaid
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Limiter {
+    pub fn new(rate: u64) -> Self {
+        Limiter {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// The most bytes to copy at a time for the rate to be kept to smoothly, rather than in bursts
+    /// of a long chunk followed by a long sleep: a sixteenth of a second's worth.
+    pub fn chunk_len(&self) -> usize {
+        (self.rate / 16).clamp(1 << 12, 1 << 24) as usize
+    }
+
+    /// Account for `bytes` having been copied, sleeping for as long as is needed for the copy to
+    /// stay within the limit.
+    pub fn consume(&self, bytes: u64) {
+        let rate = self.rate as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let accrued = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + accrued).min(rate) - bytes as f64;
+            bucket.last_refill = now;
+            Duration::from_secs_f64(-bucket.tokens.min(0.0) / rate)
+        };
+        // The lock isn't held while sleeping, so that other threads can run up debts of their own
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
@@ -21,15 +21,26 @@
 
 use dev_utils::*;
 use fcp::{self, filesystem as fs};
-use std::ffi::OsStr;
+#[cfg(target_os = "linux")]
+use nix::libc;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::ffi::{OsStr, OsString};
 use std::io::prelude::*;
-use std::process::{Command, ExitStatus};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
 use std::string::String;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 fn diff(filename: &str) -> ExitStatus {
     let filename = filename.strip_suffix(".json").unwrap();
     Command::new("diff")
-        .args(&[
+        .args([
             "-rq",
             "--no-dereference",
             HYDRATED_DIR.join(filename).to_str().unwrap(),
@@ -40,8 +51,13 @@ fn diff(filename: &str) -> ExitStatus {
 }
 
 struct CommandResult {
+    stdout: String,
+    /// Everything printed on stderr other than the summary
     stderr: String,
+    /// The line summarizing the totals of the copy printed last on stderr, if any
+    summary: Option<String>,
     success: bool,
+    code: Option<i32>,
 }
 
 fn fcp_run<T: AsRef<OsStr>>(args: &[T]) -> CommandResult {
@@ -49,17 +65,72 @@ fn fcp_run<T: AsRef<OsStr>>(args: &[T]) -> CommandResult {
         .args(args)
         .output()
         .unwrap();
+    let mut stderr = String::from_utf8(result.stderr).unwrap();
+    let last_line = stderr
+        .trim_end_matches('\n')
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let summary = if stderr[last_line..].starts_with("copied ") {
+        let summary = stderr[last_line..].trim_end().to_string();
+        stderr.truncate(last_line);
+        Some(summary)
+    } else {
+        None
+    };
     CommandResult {
-        stderr: String::from_utf8(result.stderr).unwrap(),
+        stdout: String::from_utf8(result.stdout).unwrap(),
+        stderr,
+        summary,
         success: result.status.success(),
+        code: result.status.code(),
     }
 }
 
 fn copy_fixture(filename: &str) -> CommandResult {
+    copy_fixture_with_options(filename, &[])
+}
+
+fn copy_fixture_with_options(filename: &str, options: &[&str]) -> CommandResult {
     let filename = filename.strip_suffix(".json").unwrap();
+    let source = HYDRATED_DIR.join(filename);
     let destination = COPIES_DIR.join(filename);
     remove(&destination);
-    fcp_run(&[HYDRATED_DIR.join(filename), destination])
+    let mut args: Vec<&OsStr> = options.iter().map(OsStr::new).collect();
+    args.extend(&[source.as_os_str(), destination.as_os_str()]);
+    fcp_run(&args)
+}
+
+/// Set the extended attribute `name` of `path` to `value`, returning whether it could be set.
+#[cfg(target_os = "linux")]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let name = CString::new(name).unwrap();
+    let value_ptr = value.as_ptr().cast();
+    unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0) == 0 }
+}
+
+/// The value of the extended attribute `name` of `path`, if it has one.
+#[cfg(target_os = "linux")]
+fn get_xattr(path: &Path, name: &str) -> Option<Vec<u8>> {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let name = CString::new(name).unwrap();
+    let mut value = vec![0; 256];
+    let value_ptr = value.as_mut_ptr().cast();
+    let len = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len()) };
+    if len < 0 {
+        return None;
+    }
+    value.truncate(len as usize);
+    Some(value)
+}
+
+fn touch(path: &Path, date: &str) {
+    let status = Command::new("touch")
+        .args(["-h", "-d", date])
+        .arg(path)
+        .status()
+        .unwrap();
+    assert!(status.success());
 }
 
 macro_rules! make_test {
@@ -99,8 +170,85 @@ fn socket() {
     let fixture_file = "socket.json";
     hydrate_fixture(fixture_file);
     let result = copy_fixture(fixture_file);
+    assert!(result.success);
+    assert!(result.stderr.contains("omitting socket"));
+    assert!(!COPIES_DIR.join("socket").exists());
+    let result = copy_fixture_with_options(fixture_file, &["--no-ignore-special"]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("sockets cannot be copied"));
+}
+
+#[test]
+fn omit_sockets() {
+    initialize();
+    let source = COPIES_DIR.join("omit_sockets_source");
+    let destination = COPIES_DIR.join("omit_sockets");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    let _listener = UnixListener::bind(source.join("socket")).unwrap();
+    let result = fcp_run(&[&source, &destination]);
+    assert!(result.success);
+    assert_eq!(
+        result.stderr,
+        format!(
+            "warning: omitting socket '{}'\n",
+            source.join("socket").display()
+        )
+    );
+    // The rest of the directory is still copied
+    assert_eq!(
+        std::fs::read_to_string(destination.join("file")).unwrap(),
+        "contents"
+    );
+    assert!(!destination.join("socket").exists());
+    remove(&destination);
+    let result = fcp_run(&[
+        OsStr::new("--no-ignore-special"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert_eq!(
+        result.stderr,
+        format!(
+            "{}: sockets cannot be copied\n",
+            source.join("socket").display()
+        )
+    );
+    assert!(destination.join("file").exists());
+    assert!(!destination.join("socket").exists());
+}
+
+#[test]
+fn quiet() {
+    initialize();
+    let source = COPIES_DIR.join("quiet_source");
+    let destination = COPIES_DIR.join("quiet");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    let _listener = UnixListener::bind(source.join("socket")).unwrap();
+    let run = |options: &[&str]| {
+        remove(&destination);
+        let mut args = options.to_vec();
+        args.extend([source.to_str().unwrap(), destination.to_str().unwrap()]);
+        fcp_run(&args)
+    };
+    // The warning for omitting the socket is suppressed, but errors never are
+    let result = run(&["--quiet"]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let result = run(&["-q", "--no-ignore-special"]);
     assert!(!result.success);
     assert!(result.stderr.contains("sockets cannot be copied"));
+    let result = run(&["-q", "-v"]);
+    assert!(!result.success);
+    assert_eq!(
+        result.stderr,
+        "options '--quiet' and '--verbose' are mutually exclusive\n"
+    );
 }
 
 #[test]
@@ -122,7 +270,7 @@ fn character_device() {
     remove(&destination);
     let contents = "Hello world\r";
     let result = Command::new("tests/character_device.exp")
-        .args(&[
+        .args([
             fcp_executable_path().to_str().unwrap(),
             destination.to_str().unwrap(),
             contents,
@@ -148,6 +296,16 @@ fn too_few_arguments() {
     assert!(!fcp_run(&["source"]).success);
 }
 
+#[test]
+fn exit_status() {
+    initialize();
+    let destination = COPIES_DIR.join("exit_status");
+    assert_eq!(fcp_run::<&str>(&[]).code, Some(2));
+    assert_eq!(fcp_run(&["--bogus", "source", "dest"]).code, Some(2));
+    let result = fcp_run(&["exit_status_source", destination.to_str().unwrap()]);
+    assert_eq!(result.code, Some(1));
+}
+
 #[test]
 fn source_does_not_exist() {
     initialize();
@@ -174,7 +332,7 @@ fn partial_directory() {
     assert!(result.stderr.contains("partial_directory/two.txt"));
     for file in &["one.txt", "three.txt"] {
         let result = Command::new("diff")
-            .args(&[
+            .args([
                 "-q",
                 HYDRATED_DIR
                     .join("partial_directory")
@@ -228,3 +386,3123 @@ fn copy_many_into() {
     assert_eq!(result.stderr, "");
     assert!(diff(fixture_file).success());
 }
+
+#[test]
+fn preserve_timestamps() {
+    initialize();
+    let fixture_file = "preserve_timestamps.json";
+    hydrate_fixture(fixture_file);
+    let source = HYDRATED_DIR.join("preserve_timestamps");
+    let entries = ["file.txt", "link", ""];
+    for entry in &entries {
+        touch(&source.join(entry), "2001-02-03 04:05:06.789012345");
+    }
+    let expected: Vec<_> = entries
+        .iter()
+        .map(|entry| fs::symlink_metadata(source.join(entry)).unwrap())
+        .collect();
+    let result = copy_fixture_with_options(fixture_file, &["--preserve=timestamps"]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let destination = COPIES_DIR.join("preserve_timestamps");
+    for (entry, expected) in entries.iter().zip(expected) {
+        let actual = fs::symlink_metadata(destination.join(entry)).unwrap();
+        assert_eq!(
+            (actual.mtime(), actual.mtime_nsec()),
+            (expected.mtime(), expected.mtime_nsec())
+        );
+        assert_eq!(
+            (actual.atime(), actual.atime_nsec()),
+            (expected.atime(), expected.atime_nsec())
+        );
+    }
+}
+
+#[test]
+fn sparse_file() {
+    initialize();
+    let source = COPIES_DIR.join("sparse_file_source");
+    let destination = COPIES_DIR.join("sparse_file");
+    remove(&source);
+    remove(&destination);
+    fs::create(&source, 0o644)
+        .unwrap()
+        .set_len(1 << 24)
+        .unwrap();
+    let result = fcp_run(&[&source, &destination]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let metadata = fs::symlink_metadata(&destination).unwrap();
+    assert_eq!(metadata.len(), 1 << 24);
+    assert!(metadata.blocks() * 512 < metadata.len());
+}
+
+#[test]
+fn preserve_links() {
+    initialize();
+    let source = COPIES_DIR.join("preserve_links_source");
+    let destination = COPIES_DIR.join("preserve_links");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create(source.join("original"), 0o644).unwrap();
+    for link in &["one", "two"] {
+        fs::hard_link(source.join("original"), source.join(link)).unwrap();
+    }
+    let result = fcp_run(&[
+        "--preserve=links",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let inode = |name| fs::symlink_metadata(destination.join(name)).unwrap().ino();
+    assert_eq!(inode("original"), inode("one"));
+    assert_eq!(inode("original"), inode("two"));
+}
+
+#[test]
+fn progress() {
+    initialize();
+    let source = COPIES_DIR.join("progress_source");
+    let destination = COPIES_DIR.join("progress");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    for (name, contents) in &[("one.txt", "one"), ("two.txt", "two")] {
+        fs::create(source.join(name), 0o644)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+    let result = fcp_run(&[
+        "--progress",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    // stderr isn't a terminal, so the final progress is written as a plain line
+    assert!(result.stderr.starts_with("3/3 files, 6 B/6 B"));
+}
+
+#[test]
+fn verbose() {
+    initialize();
+    let source = COPIES_DIR.join("verbose_source");
+    let destination = COPIES_DIR.join("verbose");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create(source.join("file.txt"), 0o644).unwrap();
+    let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+    let result = fcp_run(&["--verbose", source, destination]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert_eq!(
+        result.stdout,
+        format!(
+            "'{0}' -> '{1}'\n'{0}/file.txt' -> '{1}/file.txt'\n",
+            source, destination
+        )
+    );
+}
+
+#[test]
+fn dry_run() {
+    initialize();
+    let source = COPIES_DIR.join("dry_run_source");
+    let destination = COPIES_DIR.join("dry_run");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create(source.join("file.txt"), 0o644).unwrap();
+    let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+    let result = fcp_run(&["--dry-run", source, destination]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert_eq!(
+        result.stdout,
+        format!(
+            "'{0}' -> '{1}'\n'{0}/file.txt' -> '{1}/file.txt'\n",
+            source, destination
+        )
+    );
+    assert!(!Path::new(destination).exists());
+}
+
+#[test]
+fn interactive_without_terminal() {
+    initialize();
+    let source = COPIES_DIR.join("interactive_without_terminal_source");
+    let destination = COPIES_DIR.join("interactive_without_terminal");
+    std::fs::write(&source, "new").unwrap();
+    std::fs::write(&destination, "old").unwrap();
+    let result = fcp_run(&[&source, &destination]);
+    assert!(result.success);
+    assert_eq!(std::fs::read_to_string(&destination).unwrap(), "new");
+    std::fs::write(&destination, "old").unwrap();
+    let result = fcp_run(&[Path::new("-i"), &source, &destination]);
+    assert!(result.success);
+    assert_eq!(std::fs::read_to_string(&destination).unwrap(), "old");
+}
+
+#[test]
+fn no_clobber() {
+    initialize();
+    let source = COPIES_DIR.join("no_clobber_source");
+    let destination = COPIES_DIR.join("no_clobber");
+    let merged = destination.join("no_clobber_source");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("existing.txt"), "new").unwrap();
+    std::fs::write(source.join("missing.txt"), "new").unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    fs::create_dir(&merged, 0o777).unwrap();
+    std::fs::write(merged.join("existing.txt"), "old").unwrap();
+    let result = fcp_run(&[Path::new("--no-clobber"), &source, &destination]);
+    assert!(result.success);
+    let read = |file| std::fs::read_to_string(merged.join(file)).unwrap();
+    assert_eq!(read("existing.txt"), "old");
+    assert_eq!(read("missing.txt"), "new");
+}
+
+#[test]
+fn update() {
+    initialize();
+    let source = COPIES_DIR.join("update_source");
+    let destination = COPIES_DIR.join("update");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    for (file, source_date, destination_date) in [
+        ("older.txt", "2020-01-01", "2021-01-01"),
+        ("same.txt", "2021-01-01", "2021-01-01"),
+        ("newer.txt", "2022-01-01", "2021-01-01"),
+    ] {
+        std::fs::write(source.join(file), "new").unwrap();
+        std::fs::write(destination.join(file), "old").unwrap();
+        touch(&source.join(file), source_date);
+        touch(&destination.join(file), destination_date);
+    }
+    let result = fcp_run(&[
+        Path::new("--update"),
+        &source.join("older.txt"),
+        &source.join("same.txt"),
+        &source.join("newer.txt"),
+        &destination,
+    ]);
+    assert!(result.success);
+    let read = |file| std::fs::read_to_string(destination.join(file)).unwrap();
+    assert_eq!(read("older.txt"), "old");
+    assert_eq!(read("same.txt"), "old");
+    assert_eq!(read("newer.txt"), "new");
+}
+
+#[test]
+fn force() {
+    initialize();
+    let source = COPIES_DIR.join("force_source");
+    let destination = COPIES_DIR.join("force");
+    let merged = destination.join("force_source");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "new").unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    fs::symlink("target", source.join("symlink")).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    fs::create_dir(&merged, 0o777).unwrap();
+    fs::create_dir(merged.join("file"), 0o777).unwrap();
+    std::fs::write(merged.join("directory"), "old").unwrap();
+    std::fs::write(merged.join("symlink"), "old").unwrap();
+    assert!(!fcp_run(&[&source, &destination]).success);
+    let result = fcp_run(&[Path::new("--force"), &source, &destination]);
+    assert!(result.success);
+    assert_eq!(std::fs::read_to_string(merged.join("file")).unwrap(), "new");
+    assert!(merged.join("directory").is_dir());
+    assert_eq!(
+        fs::read_link(merged.join("symlink")).unwrap(),
+        Path::new("target")
+    );
+    assert_eq!(fcp_run(&["-f", "-n", "source", "dest"]).code, Some(2));
+}
+
+#[test]
+fn dereference() {
+    initialize();
+    let source = COPIES_DIR.join("dereference_source");
+    let destination = COPIES_DIR.join("dereference");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::symlink("file", source.join("link")).unwrap();
+    fs::symlink(".", source.join("loop")).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    fs::symlink("directory", source.join("directory_link")).unwrap();
+    fs::symlink("missing", source.join("dangling")).unwrap();
+    let result = fcp_run(&[Path::new("-L"), &source, &destination]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("dangling: cannot dereference"));
+    assert!(result.stderr.contains(&format!(
+        "symlink loop detected at '{}'",
+        source.join("loop").display()
+    )));
+    // A directory linked to from elsewhere in the tree is copied each time it's reached
+    assert!(destination.join("directory").is_dir());
+    assert!(destination.join("directory_link").is_dir());
+    let link = destination.join("link");
+    assert!(fs::symlink_metadata(&link).unwrap().is_file());
+    assert_eq!(std::fs::read_to_string(link).unwrap(), "contents");
+    assert!(!destination.join("dangling").exists());
+}
+
+#[test]
+fn symlink_loop() {
+    initialize();
+    let container = COPIES_DIR.join("symlink_loop");
+    remove(&container);
+    let (source, destination) = (container.join("source"), container.join("copy"));
+    fs::create_dir(&container, 0o777).unwrap();
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::symlink(".", source.join("self")).unwrap();
+    let result = fcp_run(&[Path::new("-L"), &source, &destination]);
+    assert!(!result.success);
+    assert!(result.stderr.contains(&format!(
+        "symlink loop detected at '{}'",
+        source.join("self").display()
+    )));
+    assert!(destination.join("file").is_file());
+    assert!(!destination.join("self").exists());
+    // A loop through the copy being made (which isn't an ancestor of any source) is broken there,
+    // rather than the copy being copied into itself
+    remove(&destination);
+    std::fs::remove_file(source.join("self")).unwrap();
+    fs::symlink("..", source.join("up")).unwrap();
+    let result = fcp_run(&[Path::new("-L"), &source, &destination]);
+    assert!(result.stderr.contains(&format!(
+        "symlink loop detected at '{}'",
+        source.join("up/source").display()
+    )));
+    assert!(result.stderr.contains(&format!(
+        "omitting '{}', which is a copy being made",
+        source.join("up/copy").display()
+    )));
+    assert!(destination.join("up").is_dir());
+    assert!(!destination.join("up/copy").exists());
+}
+
+#[test]
+fn no_dereference() {
+    initialize();
+    let source = COPIES_DIR.join("no_dereference_source");
+    let destination = COPIES_DIR.join("no_dereference");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, "contents").unwrap();
+    let link = COPIES_DIR.join("no_dereference_link");
+    remove(&link);
+    fs::symlink(&source, &link).unwrap();
+    let result = fcp_run(&[Path::new("-L"), Path::new("-P"), &link, &destination]);
+    assert!(result.success);
+    assert_eq!(fs::read_link(&destination).unwrap(), source);
+}
+
+#[test]
+fn archive() {
+    initialize();
+    let source = COPIES_DIR.join("archive_source");
+    let destination = COPIES_DIR.join("archive");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o750).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::hard_link(source.join("file"), source.join("link")).unwrap();
+    fs::symlink("file", source.join("symlink")).unwrap();
+    touch(&source.join("file"), "2020-01-01");
+    touch(&source.join("symlink"), "2020-01-01");
+    touch(&source, "2020-01-01");
+    let result = fcp_run(&[Path::new("-a"), &source, &destination]);
+    assert!(result.success);
+    for file in ["", "file", "symlink"] {
+        let (source, copy) = (
+            fs::symlink_metadata(source.join(file)).unwrap(),
+            fs::symlink_metadata(destination.join(file)).unwrap(),
+        );
+        assert_eq!(source.mode(), copy.mode());
+        assert_eq!(source.mtime(), copy.mtime());
+        assert_eq!((source.uid(), source.gid()), (copy.uid(), copy.gid()));
+    }
+    let (file, link) = (
+        fs::symlink_metadata(destination.join("file")).unwrap(),
+        fs::symlink_metadata(destination.join("link")).unwrap(),
+    );
+    assert_eq!(file.ino(), link.ino());
+}
+
+#[test]
+fn preserve_special() {
+    initialize();
+    let source = COPIES_DIR.join("preserve_special_source");
+    let destination = COPIES_DIR.join("preserve_special");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    // Creating device nodes (here, a copy of /dev/null) requires root
+    let created = Command::new("mknod")
+        .args(["-m", "640"])
+        .arg(source.join("null"))
+        .args(["c", "1", "3"])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    if !created.success() {
+        return;
+    }
+    let result = fcp_run(&[Path::new("--preserve=special"), &source, &destination]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let (source_metadata, copy) = (
+        fs::symlink_metadata(source.join("null")).unwrap(),
+        fs::symlink_metadata(destination.join("null")).unwrap(),
+    );
+    assert!(copy.file_type().is_char_device());
+    assert_eq!(source_metadata.rdev(), copy.rdev());
+    assert_eq!(source_metadata.mode(), copy.mode());
+    // Otherwise the contents of the device are copied as a regular file
+    remove(&destination);
+    let result = fcp_run(&[&source, &destination]);
+    assert!(result.success);
+    let copy = fs::symlink_metadata(destination.join("null")).unwrap();
+    assert!(copy.is_file());
+    assert_eq!(copy.len(), 0);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_acl() {
+    initialize();
+    let source = COPIES_DIR.join("preserve_acl_source");
+    let destination = COPIES_DIR.join("preserve_acl");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o750)).unwrap();
+    // ACLs in the format in which they're stored as extended attributes: a version number followed
+    // by each entry's tag, permissions, and (for named users and groups) ID
+    let acl = |entries: &[(u16, u16, u32)]| {
+        let mut acl = 2u32.to_le_bytes().to_vec();
+        for (tag, permissions, id) in entries {
+            acl.extend(tag.to_le_bytes());
+            acl.extend(permissions.to_le_bytes());
+            acl.extend(id.to_le_bytes());
+        }
+        acl
+    };
+    let (user_obj, user, group_obj, mask, other) = (0x01, 0x02, 0x04, 0x10, 0x20);
+    let none = u32::MAX;
+    // The mask of the access ACL becomes the group permissions of the file's mode
+    let access = acl(&[
+        (user_obj, 6, none),
+        (user, 4, 1234),
+        (group_obj, 4, none),
+        (mask, 6, none),
+        (other, 0, none),
+    ]);
+    let default = acl(&[
+        (user_obj, 7, none),
+        (user, 5, 1234),
+        (group_obj, 5, none),
+        (mask, 5, none),
+        (other, 0, none),
+    ]);
+    if !set_xattr(&source.join("file"), "system.posix_acl_access", &access)
+        || !set_xattr(&source, "system.posix_acl_default", &default)
+    {
+        return;
+    }
+    let result = fcp_run(&[
+        OsStr::new("--preserve=acl"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success, "{}", result.stderr);
+    let file = destination.join("file");
+    assert_eq!(get_xattr(&file, "system.posix_acl_access").unwrap(), access);
+    assert_eq!(
+        get_xattr(&destination, "system.posix_acl_default").unwrap(),
+        default
+    );
+    // Each still has the mode of its source (applied before the ACLs, which leave it as it is)
+    let mode = |path: &Path| fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(&file), 0o660);
+    assert_eq!(mode(&destination), 0o750);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_xattr_unpermitted() {
+    initialize();
+    let source = COPIES_DIR.join("preserve_xattr_unpermitted_source");
+    let destination = COPIES_DIR.join("preserve_xattr_unpermitted");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, "contents").unwrap();
+    assert!(set_xattr(&source, "user.fcp", b"user"));
+    // Attributes in the security namespace can only be set with CAP_SYS_ADMIN, which the copy is
+    // then run without
+    if !set_xattr(&source, "security.fcp", b"security") {
+        return;
+    }
+    let result = Command::new("setpriv")
+        .args(["--bounding-set=-sys_admin", "--inh-caps=-sys_admin"])
+        .arg(fcp_executable_path())
+        .arg("--preserve=xattr")
+        .args([&source, &destination])
+        .output();
+    let result = match result {
+        Ok(result) => result,
+        Err(_) => return,
+    };
+    // The attribute is skipped with a warning, while the rest are still copied
+    assert!(result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.starts_with(&format!(
+        "warning: {}: security.fcp: Operation not permitted",
+        destination.display()
+    )));
+    assert_eq!(get_xattr(&destination, "user.fcp").unwrap(), b"user");
+    assert_eq!(get_xattr(&destination, "security.fcp"), None);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_xattr_unsupported() {
+    initialize();
+    let source = COPIES_DIR.join("preserve_xattr_unsupported_source");
+    let mount = COPIES_DIR.join("preserve_xattr_unsupported");
+    remove(&source);
+    remove(&mount);
+    std::fs::write(&source, "contents").unwrap();
+    fs::create_dir(&mount, 0o777).unwrap();
+    if !set_xattr(&source, "user.fcp", b"user") {
+        return;
+    }
+    // ramfs doesn't support extended attributes at all, and can be mounted (in a new user and mount
+    // namespace) without privileges, where creating namespaces is permitted
+    let script = r#"mount -t ramfs none "$1" || exit 77; "$0" --preserve=xattr "$2" "$1/file" &&
+        cat "$1/file""#;
+    let result = Command::new("unshare")
+        .args(["-rm", "sh", "-c", script])
+        .arg(fcp_executable_path())
+        .args([&mount, &source])
+        .stderr(Stdio::piped())
+        .output();
+    let result = match result {
+        Ok(result) if result.status.code() != Some(77) => result,
+        _ => return,
+    };
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    if stderr.starts_with("unshare: ") {
+        return;
+    }
+    // The attribute is skipped with a warning, rather than failing the copy
+    assert!(result.status.success(), "{}", stderr);
+    assert!(stderr.starts_with(&format!(
+        "warning: {}/file: user.fcp: Operation not supported",
+        mount.display()
+    )));
+    assert_eq!(result.stdout, b"contents");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_xattr_failed() {
+    initialize();
+    let source = Path::new("/dev/shm/fcp_preserve_xattr_failed");
+    let destination = COPIES_DIR.join("preserve_xattr_failed");
+    remove(source);
+    remove(&destination);
+    if std::fs::create_dir(source).is_err() {
+        return;
+    }
+    // tmpfs stores extended attributes of any size, whereas ext4 (without `ea_inode`) only has room
+    // for those fitting within a single block
+    let set = set_xattr(source, "user.fcp", &[0; 8192]);
+    fs::set_permissions(source, PermissionsExt::from_mode(0o555)).unwrap();
+    if set {
+        let result = fcp_run(&[
+            OsStr::new("--preserve=xattr"),
+            source.as_os_str(),
+            destination.as_os_str(),
+        ]);
+        if !result.success {
+            assert!(result.stderr.contains("user.fcp"), "{}", result.stderr);
+            // The directory (created writable so as to copy its contents into) still gets its mode
+            let mode = std::fs::metadata(&destination)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o555);
+        }
+    }
+    remove(source);
+    remove(&destination);
+}
+
+#[test]
+fn one_file_system() {
+    initialize();
+    let source = COPIES_DIR.join("one_file_system_source");
+    let destination = COPIES_DIR.join("one_file_system");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    std::fs::write(source.join("directory/file"), "contents").unwrap();
+    // procfs is always mounted separately, and is made part of the tree by dereferencing a link
+    fs::symlink("/proc/sys", source.join("mount")).unwrap();
+    let result = fcp_run(&[Path::new("-xL"), &source, &destination]);
+    assert!(result.success);
+    assert!(destination.join("directory/file").exists());
+    let mount = destination.join("mount");
+    assert!(mount.is_dir());
+    assert_eq!(std::fs::read_dir(mount).unwrap().count(), 0);
+}
+
+#[test]
+fn target_directory() {
+    initialize();
+    let source = COPIES_DIR.join("target_directory_source");
+    let destination = COPIES_DIR.join("target_directory");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    let (one, two) = (source.join("one.txt"), source.join("two.txt"));
+    std::fs::write(&one, "one").unwrap();
+    std::fs::write(&two, "two").unwrap();
+    let result = fcp_run(&[Path::new("-t"), &destination, &one, &two]);
+    assert!(result.success);
+    assert!(destination.join("one.txt").exists());
+    assert!(destination.join("two.txt").exists());
+    let mut option = OsString::from("--target-directory=");
+    option.push(&one);
+    let result = fcp_run(&[option.as_os_str(), two.as_os_str()]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("is not a directory"));
+}
+
+#[test]
+fn no_target_directory() {
+    initialize();
+    let source = COPIES_DIR.join("no_target_directory_source");
+    let destination = COPIES_DIR.join("no_target_directory");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    std::fs::write(source.join("file.txt"), "contents").unwrap();
+    let result = fcp_run(&[Path::new("-T"), &source, &destination]);
+    assert!(result.success);
+    assert!(destination.join("file.txt").exists());
+    assert!(!destination.join("no_target_directory_source").exists());
+    let result = fcp_run(&[Path::new("-T"), &source, &source, &destination]);
+    assert_eq!(result.code, Some(2));
+}
+
+#[test]
+fn backup() {
+    initialize();
+    let source = COPIES_DIR.join("backup_source");
+    let destination = COPIES_DIR.join("backup");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&destination, 0o777).unwrap();
+    let file = destination.join("file");
+    let backup = |suffix: &str| {
+        let mut path = file.clone().into_os_string();
+        path.push(suffix);
+        std::fs::read_to_string(path).unwrap()
+    };
+    std::fs::write(&file, "0").unwrap();
+    for (version, control) in [
+        ("1", "--backup"),
+        ("2", "--backup=numbered"),
+        ("3", "--backup=numbered"),
+        ("4", "--backup=existing"),
+        ("5", "--backup=simple"),
+    ] {
+        std::fs::write(&source, version).unwrap();
+        assert!(fcp_run(&[OsStr::new(control), source.as_os_str(), file.as_os_str()]).success);
+    }
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "5");
+    assert_eq!(backup("~"), "4");
+    assert_eq!(backup(".~1~"), "1");
+    assert_eq!(backup(".~2~"), "2");
+    assert_eq!(backup(".~3~"), "3");
+}
+
+#[test]
+fn setuid() {
+    initialize();
+    let source = COPIES_DIR.join("setuid_source");
+    let destination = COPIES_DIR.join("setuid");
+    remove(&source);
+    remove(&destination);
+    fs::create(&source, 0o755).unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o4755)).unwrap();
+    for options in [&[][..], &["--preserve=ownership"]] {
+        remove(&destination);
+        let mut args = options.iter().map(OsStr::new).collect::<Vec<_>>();
+        args.extend([source.as_os_str(), destination.as_os_str()]);
+        assert!(fcp_run(&args).success);
+        let mode = fs::symlink_metadata(&destination).unwrap().mode();
+        assert_eq!(mode & 0o7777, 0o4755);
+    }
+}
+
+#[test]
+fn summary() {
+    initialize();
+    let source = COPIES_DIR.join("summary_source");
+    let destination = COPIES_DIR.join("summary");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("one.txt"), "one").unwrap();
+    std::fs::write(source.join("three.txt"), "three").unwrap();
+    std::os::unix::fs::symlink("one.txt", source.join("symlink")).unwrap();
+    fs::mkfifo(source.join("fifo"), PermissionsExt::from_mode(0o644)).unwrap();
+    let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+    // Printed even though stderr isn't a terminal, with each kind of file counted separately
+    let result = fcp_run(&[source, destination]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let summary = result.summary.unwrap();
+    assert!(
+        summary.starts_with("copied 2 files, 1 directory, 1 symlink, 1 special file (8 B) in "),
+        "{}",
+        summary
+    );
+    assert!(summary.ends_with(" s"));
+    // With a trailing slash, the contents of the source are copied over those of the copy
+    let contents = format!("{}/", source);
+    let result = fcp_run(&["-n", &contents, destination]);
+    assert!(result.success);
+    let summary = result.summary.unwrap();
+    assert!(summary.starts_with("copied 0 files, 1 directory (0 B) in "));
+    assert!(summary.ends_with(" s, skipping 4"));
+    remove(Path::new(destination));
+    let result = fcp_run(&["-q", source, destination]);
+    assert!(result.success);
+    assert_eq!((result.stderr, result.summary), (String::new(), None));
+    remove(Path::new(destination));
+    let result = fcp_run(&["--output=json", source, destination]);
+    assert!(result.success);
+    assert_eq!((result.stderr, result.summary), (String::new(), None));
+    assert!(result.stdout.lines().last().unwrap().starts_with(concat!(
+        r#"{"op":"summary","files":2,"directories":1,"symlinks":1,"special":1,"bytes":8,"#,
+        r#""skipped":0,"identical":0,"errors":0,"seconds":"#
+    )));
+}
+
+#[test]
+fn json_output() {
+    initialize();
+    let source = COPIES_DIR.join("json_output_source");
+    let destination = COPIES_DIR.join("json_output");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, "contents").unwrap();
+    let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+    let result = fcp_run(&["--output=json", source, destination]);
+    assert!(result.success);
+    let (copied, summary) = result.stdout.split_once('\n').unwrap();
+    assert_eq!(
+        copied,
+        format!(
+            r#"{{"op":"copy","src":"{}","dst":"{}","bytes":8}}"#,
+            source, destination
+        )
+    );
+    // Followed by the (variable) number of seconds the copy took
+    assert!(summary.starts_with(concat!(
+        r#"{"op":"summary","files":1,"directories":0,"symlinks":0,"special":0,"bytes":8,"#,
+        r#""skipped":0,"identical":0,"errors":0,"seconds":"#
+    )));
+    assert!(summary.ends_with("}\n"));
+    let result = fcp_run(&["--output=json", "json_output_missing", destination]);
+    assert!(!result.success);
+    assert_eq!(result.stderr, "");
+    assert!(result.stdout.starts_with(
+        r#"{"op":"error","src":"json_output_missing","message":"json_output_missing: "#
+    ));
+    assert!(result.stdout.contains(r#""kind":"not_found"}"#));
+}
+
+#[test]
+fn library_copy() {
+    initialize();
+    let source = COPIES_DIR.join("library_copy_source");
+    let destination = COPIES_DIR.join("library_copy");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("one.txt"), "one").unwrap();
+    std::fs::write(source.join("three.txt"), "three").unwrap();
+    let copied = Arc::new(AtomicU64::new(0));
+    let options = fcp::CopyOptions {
+        progress: Some(Arc::new({
+            let copied = Arc::clone(&copied);
+            move |bytes| {
+                copied.fetch_add(bytes, Ordering::Relaxed);
+            }
+        })),
+        ..Default::default()
+    };
+    let summary = fcp::copy(&[source], &destination, options).unwrap();
+    assert_eq!(summary.status, fcp::Status::Success);
+    assert_eq!((summary.files, summary.directories), (2, 1));
+    assert_eq!((summary.bytes, summary.errors), (8, 0));
+    assert_eq!(copied.load(Ordering::Relaxed), 8);
+    assert!(destination.join("three.txt").exists());
+}
+
+#[test]
+fn jobs() {
+    initialize();
+    let source = COPIES_DIR.join("jobs_source");
+    let destination = COPIES_DIR.join("jobs");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    for name in ["c", "a", "b"] {
+        std::fs::write(source.join(name), name).unwrap();
+    }
+    let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+    let result = fcp_run(&["-vj1", source, destination]);
+    assert!(result.success);
+    let expected: String = ["", "/a", "/b", "/c"]
+        .iter()
+        .map(|name| format!("'{}{}' -> '{}{}'\n", source, name, destination, name))
+        .collect();
+    assert_eq!(result.stdout, expected);
+    assert!(!fcp_run(&["--jobs=0", source, destination]).success);
+}
+
+#[test]
+fn buffer_size() {
+    initialize();
+    let source = COPIES_DIR.join("buffer_size_source");
+    let destination = COPIES_DIR.join("buffer_size");
+    remove(&destination);
+    let contents: String = (0..10_000).map(|i| i.to_string()).collect();
+    std::fs::write(&source, &contents).unwrap();
+    let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+    assert!(fcp_run(&["--buffer-size=1K", source, destination]).success);
+    assert_eq!(std::fs::read_to_string(destination).unwrap(), contents);
+    for size in ["0", "1X", "K"] {
+        let result = fcp_run(&[&format!("--buffer-size={}", size), source, destination]);
+        assert_eq!(result.code, Some(fcp::Status::Usage as i32));
+    }
+}
+
+#[test]
+fn cross_device_rename() {
+    initialize();
+    let other_device = Path::new("/dev/shm");
+    match fs::metadata(other_device) {
+        Ok(metadata) if metadata.dev() != fs::metadata(&*COPIES_DIR).unwrap().dev() => {}
+        // There's no other filesystem to rename across
+        _ => return,
+    }
+    let source = COPIES_DIR.join("cross_device_rename");
+    let destination = other_device.join("fcp_cross_device_rename");
+    remove(&destination);
+    std::fs::write(&source, "contents").unwrap();
+    fs::rename(&source, &destination).unwrap();
+    assert!(!source.exists());
+    assert_eq!(std::fs::read_to_string(&destination).unwrap(), "contents");
+    remove(&destination);
+}
+
+#[test]
+fn parallel_file() {
+    initialize();
+    let source = COPIES_DIR.join("parallel_file_source");
+    let destination = COPIES_DIR.join("parallel_file");
+    remove(&source);
+    remove(&destination);
+    // Data at either end of a hole, with the final chunk left short
+    let mut contents: Vec<u8> = (0..1 << 16).map(|i| i as u8).collect();
+    contents.resize(1 << 22, 0);
+    contents.extend((0..12_345).map(|i| (i % 251) as u8));
+    std::fs::write(&source, &contents).unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--parallel-file-threshold=1M"),
+        OsStr::new("--parallel-file-chunks=3"),
+        OsStr::new("--sparse=always"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    let metadata = fs::symlink_metadata(&destination).unwrap();
+    assert!(metadata.blocks() * 512 < metadata.len());
+}
+
+#[test]
+fn bwlimit() {
+    initialize();
+    let source = COPIES_DIR.join("bwlimit_source");
+    let destination = COPIES_DIR.join("bwlimit");
+    remove(&destination);
+    std::fs::write(&source, vec![1; 1 << 17]).unwrap();
+    let start = Instant::now();
+    let result = fcp_run(&[
+        OsStr::new("--bwlimit=64K"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    // A second's worth may be copied at once, with the remainder taking another second
+    assert!(start.elapsed() >= Duration::from_millis(900));
+    assert_eq!(std::fs::read(&destination).unwrap().len(), 1 << 17);
+}
+
+#[test]
+fn bwlimit_smooth() {
+    initialize();
+    let source = COPIES_DIR.join("bwlimit_smooth_source");
+    let destination = COPIES_DIR.join("bwlimit_smooth");
+    remove(&destination);
+    // Longer than the chunks copied within the kernel at a time when unthrottled
+    std::fs::write(&source, vec![1; 20 << 20]).unwrap();
+    let start = Instant::now();
+    let mut child = Command::new(fcp_executable_path())
+        .args(["--bwlimit=8M", "--quiet"])
+        .args([&source, &destination])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    // Copied at a steady rate once the second's worth copied at first is spent, rather than in
+    // bursts of a whole chunk at a time
+    let copied = fs::symlink_metadata(&destination).map_or(0, |metadata| metadata.len());
+    assert!(copied > 0 && copied <= 13 << 20, "{}", copied);
+    assert!(child.wait().unwrap().success());
+    assert!(start.elapsed() >= Duration::from_millis(1400));
+    assert_eq!(std::fs::read(&destination).unwrap().len(), 20 << 20);
+}
+
+#[test]
+fn checksum() {
+    initialize();
+    let source = COPIES_DIR.join("checksum_source");
+    let destination = COPIES_DIR.join("checksum");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--checksum"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    // The contents of procfs files change from one read to the next
+    let result = fcp_run(&[
+        "--checksum",
+        "--output=json",
+        "/proc/self/stat",
+        destination.join("stat").to_str().unwrap(),
+    ]);
+    assert!(!result.success);
+    assert!(result.stdout.contains(r#""kind":"checksum_mismatch""#));
+}
+
+#[test]
+fn resume() {
+    initialize();
+    let source = COPIES_DIR.join("resume_source");
+    let destination = COPIES_DIR.join("resume");
+    remove(&destination);
+    let contents: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&source, &contents).unwrap();
+    let metadata = std::fs::metadata(&source).unwrap();
+    let args = [
+        OsStr::new("--resume"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ];
+    // A copy which fails partway through is left with the modification time of its source
+    let result = Command::new("sh")
+        .args(["-c", "trap '' XFSZ; ulimit -f 40; exec \"$0\" \"$@\""])
+        .arg(fcp_executable_path())
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    let partial = std::fs::metadata(&destination).unwrap();
+    assert!(partial.len() < contents.len() as u64);
+    assert_eq!(partial.modified().unwrap(), metadata.modified().unwrap());
+    assert!(fcp_run(&args).success);
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    // The contents of such a partial copy are trusted, so that whatever it contains is kept
+    std::fs::write(&destination, vec![0; 40_000]).unwrap();
+    fs::set_timestamps(&destination, &metadata).unwrap();
+    // Preserving timestamps, so that the complete copy is then skipped in turn
+    assert!(fcp_run(&[&[OsStr::new("-p")][..], &args].concat()).success);
+    let copied = std::fs::read(&destination).unwrap();
+    assert_eq!(copied[..40_000], [0; 40_000]);
+    assert_eq!(copied[40_000..], contents[40_000..]);
+    let result = fcp_run(&[&[OsStr::new("-v")][..], &args].concat());
+    assert!(result.success);
+    assert_eq!(
+        result.stdout,
+        format!("skipped '{}'\n", destination.display())
+    );
+    // Unless they're verified, in which case a copy whose contents differ is copied over
+    assert!(fcp_run(&[&[OsStr::new("--checksum")][..], &args].concat()).success);
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    // As is one modified since it was left partially copied
+    std::fs::write(&destination, vec![0; 40_000]).unwrap();
+    touch(&destination, "2000-01-01");
+    assert!(fcp_run(&args).success);
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    // Whereas one whose contents are verified to match the start of the source is resumed
+    std::fs::write(&destination, &contents[..40_000]).unwrap();
+    touch(&destination, "2000-01-01");
+    assert!(fcp_run(&[&[OsStr::new("--checksum")][..], &args].concat()).success);
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+}
+
+#[test]
+fn glob() {
+    initialize();
+    let source = COPIES_DIR.join("glob_source");
+    let destination = COPIES_DIR.join("glob");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    for name in ["one.log", "two.log", "three.txt"] {
+        std::fs::write(source.join(name), name).unwrap();
+    }
+    let pattern = source.join("*.log");
+    let (pattern, destination) = (pattern.to_str().unwrap(), destination.to_str().unwrap());
+    let result = fcp_run(&["--glob", "-t", destination, pattern]);
+    assert!(result.success);
+    let mut copied: Vec<_> = std::fs::read_dir(destination)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    copied.sort();
+    assert_eq!(copied, ["one.log", "two.log"]);
+    let result = fcp_run(&["--glob", "glob_missing/*", destination]);
+    assert_eq!(result.code, Some(fcp::Status::Failure as i32));
+    assert!(result.stderr.contains("no files match 'glob_missing/*'"));
+}
+
+#[test]
+fn exclude() {
+    initialize();
+    let source = COPIES_DIR.join("exclude_source");
+    let destination = COPIES_DIR.join("exclude");
+    remove(&source);
+    remove(&destination);
+    for directory in [
+        "node_modules/package",
+        "src/node_modules",
+        "src/.git",
+        "docs",
+    ] {
+        std::fs::create_dir_all(source.join(directory)).unwrap();
+    }
+    for file in [
+        "src/main.rs",
+        "src/notes.txt",
+        "docs/keep.txt",
+        "docs/drop.txt",
+    ] {
+        std::fs::write(source.join(file), file).unwrap();
+    }
+    let result = fcp_run(&[
+        OsStr::new("--exclude=node_modules"),
+        OsStr::new("--exclude=.git"),
+        OsStr::new("--exclude"),
+        OsStr::new("*/*.txt"),
+        OsStr::new("--include=keep.txt"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert!(destination.join("src/main.rs").exists());
+    assert!(destination.join("docs/keep.txt").exists());
+    for excluded in [
+        "node_modules",
+        "src/node_modules",
+        "src/.git",
+        "src/notes.txt",
+        "docs/drop.txt",
+    ] {
+        assert!(!destination.join(excluded).exists(), "{}", excluded);
+    }
+}
+
+#[test]
+fn max_depth() {
+    initialize();
+    let source = COPIES_DIR.join("max_depth_source");
+    let destination = COPIES_DIR.join("max_depth");
+    remove(&source);
+    remove(&destination);
+    std::fs::create_dir_all(source.join("one/two")).unwrap();
+    std::fs::write(source.join("file"), "file").unwrap();
+    std::fs::write(source.join("one/file"), "file").unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--max-depth=1"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert!(destination.join("file").is_file());
+    assert!(destination.join("one").is_dir());
+    assert_eq!(
+        std::fs::read_dir(destination.join("one")).unwrap().count(),
+        0
+    );
+    remove(&destination);
+    let result = fcp_run(&[
+        OsStr::new("--max-depth=0"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(std::fs::read_dir(&destination).unwrap().count(), 0);
+}
+
+#[test]
+fn merge_conflicts() {
+    initialize();
+    let source = COPIES_DIR.join("merge_conflicts_source");
+    let destination = COPIES_DIR.join("merge_conflicts");
+    remove(&source);
+    remove(&destination);
+    std::fs::create_dir_all(source.join("directory")).unwrap();
+    std::fs::write(source.join("file"), "file").unwrap();
+    std::fs::create_dir_all(destination.join("file")).unwrap();
+    std::fs::write(destination.join("directory"), "directory").unwrap();
+    fs::set_permissions(&destination, PermissionsExt::from_mode(0o700)).unwrap();
+    let args = [source.as_os_str(), destination.as_os_str()];
+    let result = fcp_run(&[OsStr::new("-T"), args[0], args[1]]);
+    assert!(!result.success);
+    assert!(result.stderr.contains(&format!(
+        "cannot overwrite directory '{}' with non-directory",
+        destination.join("file").display()
+    )));
+    assert!(result.stderr.contains(&format!(
+        "cannot overwrite non-directory '{}' with directory",
+        destination.join("directory").display()
+    )));
+    // The existing directory merged into keeps its mode
+    let mode = fs::symlink_metadata(&destination)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o700);
+    let result = fcp_run(&[OsStr::new("-Tf"), args[0], args[1]]);
+    assert!(result.success);
+    assert!(destination.join("file").is_file());
+    assert!(destination.join("directory").is_dir());
+}
+
+#[test]
+fn merge_twice() {
+    initialize();
+    let source = COPIES_DIR.join("merge_twice_source");
+    let destination = COPIES_DIR.join("merge_twice");
+    remove(&source);
+    remove(&destination);
+    std::fs::create_dir_all(source.join("directory")).unwrap();
+    std::fs::write(source.join("directory/file"), "file").unwrap();
+    fs::symlink("directory/file", source.join("symlink")).unwrap();
+    fs::mkfifo(source.join("fifo"), PermissionsExt::from_mode(0o644)).unwrap();
+    let args = [
+        OsStr::new("-T"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ];
+    assert!(fcp_run(&args).success);
+    // Copying over the earlier copy replaces its symlinks and FIFOs, rather than failing on them
+    std::fs::remove_file(source.join("symlink")).unwrap();
+    fs::symlink("directory", source.join("symlink")).unwrap();
+    let result = fcp_run(&args);
+    assert!(result.success, "{}", result.stderr);
+    let target = std::fs::read_link(destination.join("symlink")).unwrap();
+    assert_eq!(target, Path::new("directory"));
+    assert!(fs::symlink_metadata(destination.join("fifo"))
+        .unwrap()
+        .file_type()
+        .is_fifo());
+    // Unless clobbering is disallowed
+    std::fs::remove_file(source.join("symlink")).unwrap();
+    fs::symlink("missing", source.join("symlink")).unwrap();
+    let result = fcp_run(&[OsStr::new("-Tn"), args[1], args[2]]);
+    assert!(result.success, "{}", result.stderr);
+    let target = std::fs::read_link(destination.join("symlink")).unwrap();
+    assert_eq!(target, Path::new("directory"));
+    // Files of other types are still in the way, with the error naming the symlink copied (rather
+    // than its target)
+    std::fs::remove_file(destination.join("symlink")).unwrap();
+    std::fs::write(destination.join("symlink"), "file").unwrap();
+    let result = fcp_run(&args);
+    assert!(!result.success);
+    assert!(result.stderr.starts_with(&format!(
+        "{}, {}: File exists",
+        source.join("symlink").display(),
+        destination.join("symlink").display()
+    )));
+}
+
+#[test]
+fn stdout() {
+    initialize();
+    let source = COPIES_DIR.join("stdout_source");
+    remove(&source);
+    std::fs::write(&source, "contents").unwrap();
+    let result = fcp_run(&[source.as_os_str(), OsStr::new("-")]);
+    assert!(result.success);
+    assert_eq!(result.stdout, "contents");
+    let result = fcp_run(&[COPIES_DIR.as_os_str(), OsStr::new("-")]);
+    assert_eq!(result.code, Some(fcp::Status::Usage as i32));
+    let result = fcp_run(&[source.as_os_str(), source.as_os_str(), OsStr::new("-")]);
+    assert_eq!(result.code, Some(fcp::Status::Usage as i32));
+    // Appended to a file opened for appending (as with `>>`), which can't be copied to within the
+    // kernel by every means
+    let appended = COPIES_DIR.join("stdout_appended");
+    std::fs::write(&appended, "existing ").unwrap();
+    let stdout = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&appended)
+        .unwrap();
+    let status = Command::new(fcp_executable_path())
+        .args([source.as_os_str(), OsStr::new("-")])
+        .stdout(stdout)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&appended).unwrap(),
+        "existing contents"
+    );
+}
+
+#[test]
+fn sendfile() {
+    initialize();
+    let source = COPIES_DIR.join("sendfile_source");
+    remove(&source);
+    // Longer than a pipe can hold (and not a whole number of pages), so that it's copied in
+    // several calls
+    let contents: String = (0..(3 << 20) + 123)
+        .map(|i: u32| char::from(b'a' + (i % 26) as u8))
+        .collect();
+    std::fs::write(&source, &contents).unwrap();
+    // `copy_file_range` can't copy to a pipe, whereas `sendfile` can
+    let result = fcp_run(&[source.as_os_str(), OsStr::new("-")]);
+    assert!(result.success);
+    assert!(result.stdout == contents);
+    // Nor (on older kernels, or between filesystems of different types) can it copy between
+    // filesystems; /dev/shm is typically a tmpfs, separate from the one being copied within
+    let shm = Path::new("/dev/shm");
+    let separate = match (fs::symlink_metadata(shm), fs::symlink_metadata(&source)) {
+        (Ok(shm), Ok(source)) => shm.is_dir() && shm.dev() != source.dev(),
+        _ => false,
+    };
+    if !separate {
+        return;
+    }
+    let destination = shm.join(format!("fcp_sendfile_{}", std::process::id()));
+    let result = fcp_run(&[&source, &destination]);
+    let copied = std::fs::read_to_string(&destination);
+    let _ = std::fs::remove_file(&destination);
+    assert!(result.success);
+    assert!(copied.unwrap() == contents);
+}
+
+#[test]
+fn files_from() {
+    initialize();
+    let source = COPIES_DIR.join("files_from_source");
+    let destination = COPIES_DIR.join("files_from");
+    let list = COPIES_DIR.join("files_from_list");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    for name in ["one", "two"] {
+        std::fs::write(source.join(name), name).unwrap();
+    }
+    let listed = [
+        source.join("one"),
+        source.join("missing"),
+        source.join("two"),
+    ];
+    let listed: Vec<_> = listed.iter().map(|path| path.to_str().unwrap()).collect();
+    std::fs::write(&list, listed.join("\n") + "\n").unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--files-from"),
+        list.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("missing"));
+    assert_eq!(
+        std::fs::read_to_string(destination.join("one")).unwrap(),
+        "one"
+    );
+    assert_eq!(
+        std::fs::read_to_string(destination.join("two")).unwrap(),
+        "two"
+    );
+}
+
+#[test]
+fn null_separated_files_from() {
+    initialize();
+    let source = COPIES_DIR.join("null_separated_files_from_source");
+    let destination = COPIES_DIR.join("null_separated_files_from");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    // Neither a newline nor invalid UTF-8 is any obstacle to a path being listed
+    let name = OsStr::from_bytes(b"new\nline\xff");
+    std::fs::write(source.join(name), "contents").unwrap();
+    let mut child = Command::new(fcp_executable_path())
+        .args([
+            OsStr::new("-0t"),
+            destination.as_os_str(),
+            OsStr::new("--files-from=-"),
+        ])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut listed = source.join(name).into_os_string().into_vec();
+    listed.push(b'\0');
+    child.stdin.take().unwrap().write_all(&listed).unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(
+        std::fs::read_to_string(destination.join(name)).unwrap(),
+        "contents"
+    );
+}
+
+#[test]
+fn keep_going() {
+    initialize();
+    let source = COPIES_DIR.join("keep_going_source");
+    let destination = COPIES_DIR.join("keep_going");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::symlink("missing", source.join("dangling")).unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--keep-going"),
+        OsStr::new("-L"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert!(destination.join("file").exists());
+    let (during, summary) = result.stderr.split_once("\n1 error occurred:\n").unwrap();
+    let dangling = source.join("dangling");
+    assert!(during.contains(&*dangling.to_string_lossy()));
+    assert!(summary.starts_with(&format!("    {}", dangling.display())));
+}
+
+#[test]
+fn dereference_arguments() {
+    initialize();
+    let source = COPIES_DIR.join("dereference_arguments_source");
+    let link = COPIES_DIR.join("dereference_arguments_link");
+    let destination = COPIES_DIR.join("dereference_arguments");
+    remove(&source);
+    remove(&link);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::symlink("file", source.join("inner_link")).unwrap();
+    fs::symlink("dereference_arguments_source", &link).unwrap();
+    let result = fcp_run(&[Path::new("-H"), &link, &destination]);
+    assert!(result.success);
+    assert!(fs::symlink_metadata(&destination).unwrap().is_dir());
+    assert_eq!(
+        fs::read_link(destination.join("inner_link")).unwrap(),
+        Path::new("file")
+    );
+    // Whichever of -H, -L, and -P is given last takes effect
+    remove(&destination);
+    let result = fcp_run(&[Path::new("-HP"), &link, &destination]);
+    assert!(result.success);
+    assert_eq!(
+        fs::read_link(&destination).unwrap(),
+        Path::new("dereference_arguments_source")
+    );
+}
+
+#[test]
+fn dangling_fifo() {
+    initialize();
+    let source = COPIES_DIR.join("dangling_fifo_source");
+    let destination = COPIES_DIR.join("dangling_fifo");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    let permissions = PermissionsExt::from_mode(0o640);
+    fs::mkfifo(source.join("fifo"), permissions).unwrap();
+    // Nothing ever opens the FIFO for writing, so reading it would block forever
+    let mut child = Command::new(fcp_executable_path())
+        .args([&source, &destination])
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("copying a FIFO without a writer didn't finish");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert!(status.success());
+    let metadata = fs::symlink_metadata(destination.join("fifo")).unwrap();
+    assert!(metadata.file_type().is_fifo());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+}
+
+#[test]
+fn copy_contents() {
+    initialize();
+    let source = COPIES_DIR.join("copy_contents_source");
+    let destination = COPIES_DIR.join("copy_contents");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    let fifo = source.join("fifo");
+    fs::mkfifo(&fifo, PermissionsExt::from_mode(0o644)).unwrap();
+    let writer = std::thread::spawn(move || std::fs::write(fifo, "contents").unwrap());
+    let result = fcp_run(&[
+        OsStr::new("--copy-contents"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    writer.join().unwrap();
+    assert!(result.success);
+    let metadata = fs::symlink_metadata(destination.join("fifo")).unwrap();
+    assert!(metadata.file_type().is_file());
+    assert_eq!(
+        std::fs::read_to_string(destination.join("fifo")).unwrap(),
+        "contents"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn interrupted_system_calls() {
+    use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+    use nix::unistd::Pid;
+    use std::sync::atomic::AtomicBool;
+    initialize();
+    let source = COPIES_DIR.join("interrupted_system_calls_source");
+    let destination = COPIES_DIR.join("interrupted_system_calls");
+    remove(&source);
+    remove(&destination);
+    let contents: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(&source, &contents).unwrap();
+    // Each read from the pipe (opened by `open`) empties it a little at a time, so that writing to
+    // it keeps blocking
+    fn read_slowly<R: Read>(
+        open: impl FnOnce() -> R + Send + 'static,
+    ) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let (mut pipe, mut read, mut chunk) = (open(), Vec::new(), vec![0; 1 << 14]);
+            loop {
+                match pipe.read(&mut chunk).unwrap() {
+                    0 => return read,
+                    len => read.extend_from_slice(&chunk[..len]),
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+    // Stopping and continuing the copy while it's blocked (as with ^Z and fg) doesn't fail it
+    let mut child = Command::new(fcp_executable_path())
+        .args([source.as_os_str(), OsStr::new("-")])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let reader = read_slowly(move || stdout);
+    let pid = Pid::from_raw(child.id() as i32);
+    while child.try_wait().unwrap().is_none() {
+        let _ = signal::kill(pid, Signal::SIGSTOP);
+        std::thread::sleep(Duration::from_millis(1));
+        let _ = signal::kill(pid, Signal::SIGCONT);
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    assert!(child.wait().unwrap().success());
+    assert!(reader.join().unwrap() == contents);
+    // Nor does a signal whose handler doesn't restart system calls interrupting them with EINTR,
+    // which only a handler within the copying process can do
+    extern "C" fn handle(_: libc::c_int) {}
+    let action = SigAction::new(
+        SigHandler::Handler(handle),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    unsafe { signal::sigaction(Signal::SIGUSR1, &action) }.unwrap();
+    fs::mkfifo(&destination, PermissionsExt::from_mode(0o644)).unwrap();
+    let reader = read_slowly({
+        let destination = destination.clone();
+        move || std::fs::File::open(destination).unwrap()
+    });
+    let copying = unsafe { libc::pthread_self() };
+    let finished = Arc::new(AtomicBool::new(false));
+    let interrupter = std::thread::spawn({
+        let finished = Arc::clone(&finished);
+        move || {
+            while !finished.load(Ordering::Relaxed) {
+                unsafe { libc::pthread_kill(copying, libc::SIGUSR1) };
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    });
+    let copied = fs::copy(
+        &source,
+        &destination,
+        0o644,
+        None,
+        None,
+        None,
+        false,
+        true,
+        |_| {},
+    );
+    finished.store(true, Ordering::Relaxed);
+    interrupter.join().unwrap();
+    assert_eq!(copied.unwrap(), contents.len() as u64);
+    assert!(reader.join().unwrap() == contents);
+}
+
+#[test]
+fn delete() {
+    initialize();
+    let source = COPIES_DIR.join("delete_source");
+    let destination = COPIES_DIR.join("delete");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    std::fs::write(source.join("directory/file"), "contents").unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    fs::create_dir(destination.join("directory"), 0o777).unwrap();
+    fs::create_dir(destination.join("extraneous_directory"), 0o777).unwrap();
+    for extraneous in [
+        "extraneous",
+        "extraneous_directory/file",
+        "directory/extraneous",
+        "excluded.log",
+    ] {
+        std::fs::write(destination.join(extraneous), "extraneous").unwrap();
+    }
+    let run = |dry_run: &str| {
+        fcp_run(&[
+            OsStr::new("--delete"),
+            OsStr::new("--exclude=*.log"),
+            OsStr::new("-v"),
+            OsStr::new(dry_run),
+            source.join("").as_os_str(),
+            destination.as_os_str(),
+        ])
+    };
+    let removed = [
+        destination.join("extraneous"),
+        destination.join("extraneous_directory"),
+        destination.join("directory/extraneous"),
+    ];
+    // A dry run only reports what would be removed
+    let result = run("--dry-run");
+    assert!(result.success, "{}", result.stderr);
+    for path in &removed {
+        assert!(result
+            .stdout
+            .contains(&format!("removed '{}'", path.display())));
+        assert!(fs::symlink_metadata(path).is_ok());
+    }
+    let result = run("--");
+    assert!(result.success, "{}", result.stderr);
+    for path in &removed {
+        assert!(result
+            .stdout
+            .contains(&format!("removed '{}'", path.display())));
+        assert!(fs::symlink_metadata(path).is_err());
+    }
+    assert_eq!(
+        std::fs::read_to_string(destination.join("directory/file")).unwrap(),
+        "contents"
+    );
+    assert!(fs::symlink_metadata(destination.join("excluded.log")).is_ok());
+}
+
+#[test]
+fn secure_dest() {
+    initialize();
+    let source = COPIES_DIR.join("secure_dest_source");
+    let destination = COPIES_DIR.join("secure_dest");
+    let outside = COPIES_DIR.join("secure_dest_outside");
+    remove(&source);
+    remove(&destination);
+    remove(&outside);
+    fs::create_dir(&outside, 0o777).unwrap();
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    let fifo = source.join("directory/fifo");
+    fs::mkfifo(&fifo, PermissionsExt::from_mode(0o644)).unwrap();
+    std::fs::write(source.join("directory/file"), "contents").unwrap();
+    // Once the FIFO is opened, its directory has been copied, and is moved aside and replaced with
+    // a symlink before the file after it is copied, which is still copied into the directory (as
+    // it's held open) rather than through the symlink
+    let (copy, target) = (
+        destination.join("directory"),
+        outside.canonicalize().unwrap(),
+    );
+    let writer = std::thread::spawn(move || {
+        let mut fifo = std::fs::OpenOptions::new().write(true).open(fifo).unwrap();
+        std::fs::rename(&copy, copy.with_file_name("moved")).unwrap();
+        std::os::unix::fs::symlink(target, &copy).unwrap();
+        fifo.write_all(b"contents").unwrap();
+    });
+    let result = fcp_run(&[
+        OsStr::new("--secure-dest"),
+        OsStr::new("--copy-contents"),
+        OsStr::new("-j1"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    writer.join().unwrap();
+    assert!(result.success, "{}", result.stderr);
+    assert!(fs::symlink_metadata(outside.join("file")).is_err());
+    assert_eq!(
+        std::fs::read_to_string(destination.join("moved/file")).unwrap(),
+        "contents"
+    );
+    // Only the directories whose entries are being copied are held open, rather than every
+    // directory yet to be finished, so a tree deeper than the limit on open files allows for is
+    // still copied
+    remove(&source);
+    remove(&destination);
+    let deep = (0..64).fold(source.clone(), |path, _| path.join("d"));
+    std::fs::create_dir_all(&deep).unwrap();
+    std::fs::write(deep.join("file"), "contents").unwrap();
+    let output = Command::new("sh")
+        .args(["-c", "ulimit -n 48; exec \"$0\" \"$@\""])
+        .arg(fcp_executable_path())
+        .args(["--secure-dest", "-j2"])
+        .args([&source, &destination])
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let copy = (0..64).fold(destination.clone(), |path, _| path.join("d"));
+    assert_eq!(
+        std::fs::read_to_string(copy.join("file")).unwrap(),
+        "contents"
+    );
+}
+
+#[test]
+fn atime_preserve() {
+    initialize();
+    let source = COPIES_DIR.join("atime_preserve_source");
+    let destination = COPIES_DIR.join("atime_preserve");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    // Reading a file last accessed over a day ago updates its access time even under `relatime`
+    let status = Command::new("touch")
+        .args(["-a", "-d", "2020-01-01"])
+        .arg(source.join("file"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let atime = fs::symlink_metadata(source.join("file")).unwrap().atime();
+    let result = fcp_run(&[
+        OsStr::new("--atime-preserve"),
+        OsStr::new("-p"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert_eq!(
+        fs::symlink_metadata(source.join("file")).unwrap().atime(),
+        atime
+    );
+    assert_eq!(
+        fs::symlink_metadata(destination.join("file"))
+            .unwrap()
+            .atime(),
+        atime
+    );
+}
+
+#[test]
+fn no_preserve_mode() {
+    initialize();
+    let source = COPIES_DIR.join("no_preserve_mode_source");
+    let destination = COPIES_DIR.join("no_preserve_mode");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o777)).unwrap();
+    fs::set_permissions(source.join("file"), PermissionsExt::from_mode(0o777)).unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--no-preserve=mode"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    // Read from a shell, as reading it here would briefly change it for every other test
+    let umask = Command::new("sh").args(["-c", "umask"]).output().unwrap();
+    let umask = u32::from_str_radix(String::from_utf8(umask.stdout).unwrap().trim(), 8).unwrap();
+    let mode = |path: &Path| fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(&destination), 0o777 & !umask);
+    assert_eq!(mode(&destination.join("file")), 0o666 & !umask);
+    // Other attributes are still preserved alongside it
+    touch(&source.join("file"), "2020-01-01");
+    remove(&destination);
+    let result = fcp_run(&[
+        OsStr::new("--no-preserve=mode"),
+        OsStr::new("-p"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    let (source_metadata, destination_metadata) = (
+        fs::symlink_metadata(source.join("file")).unwrap(),
+        fs::symlink_metadata(destination.join("file")).unwrap(),
+    );
+    assert_eq!(source_metadata.mtime(), destination_metadata.mtime());
+    assert_eq!(mode(&destination.join("file")), 0o666 & !umask);
+    // The copy is created with that mode, rather than being given it once it's finished, as seen
+    // in one which fails partway through (and is left in place to be resumed)
+    std::fs::write(source.join("file"), vec![0; 100_000]).unwrap();
+    remove(&destination);
+    let result = Command::new("sh")
+        .args(["-c", "trap '' XFSZ; ulimit -f 40; exec \"$0\" \"$@\""])
+        .arg(fcp_executable_path())
+        .args(["--no-preserve=mode", "--resume"])
+        .args([source.join("file"), destination.clone()])
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    assert_eq!(mode(&destination), 0o666 & !umask);
+}
+
+#[test]
+fn no_preserve_archive() {
+    initialize();
+    let source = COPIES_DIR.join("no_preserve_archive_source");
+    let destination = COPIES_DIR.join("no_preserve_archive");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, "contents").unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o751)).unwrap();
+    touch(&source, "2000-01-01 00:00:00");
+    // The attributes are excluded even when -a comes after --no-preserve
+    for args in [
+        ["--no-preserve=timestamps", "-a"],
+        ["-a", "--no-preserve=timestamps"],
+        ["--no-preserve=timestamps", "--preserve=mode,timestamps"],
+    ] {
+        remove(&destination);
+        let mut args: Vec<&OsStr> = args.iter().map(OsStr::new).collect();
+        args.extend([source.as_os_str(), destination.as_os_str()]);
+        let result = fcp_run(&args);
+        assert!(result.success, "{:?}: {}", args, result.stderr);
+        let (source_metadata, destination_metadata) = (
+            fs::symlink_metadata(&source).unwrap(),
+            fs::symlink_metadata(&destination).unwrap(),
+        );
+        assert_ne!(source_metadata.mtime(), destination_metadata.mtime());
+        assert_eq!(destination_metadata.permissions().mode() & 0o7777, 0o751);
+    }
+}
+
+#[test]
+fn atomic() {
+    initialize();
+    let source = COPIES_DIR.join("atomic_source");
+    let destination = COPIES_DIR.join("atomic");
+    let old = COPIES_DIR.join("atomic_old");
+    remove(&source);
+    remove(&destination);
+    remove(&old);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    std::fs::write(source.join("file"), "new contents").unwrap();
+    std::fs::write(destination.join("file"), "old contents").unwrap();
+    fs::hard_link(destination.join("file"), &old).unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--atomic"),
+        OsStr::new("-T"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(
+        std::fs::read_to_string(destination.join("file")).unwrap(),
+        "new contents"
+    );
+    // The existing file was replaced rather than overwritten in place
+    assert_eq!(std::fs::read_to_string(&old).unwrap(), "old contents");
+    let entries: Vec<_> = std::fs::read_dir(&destination)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, [OsStr::new("file")]);
+}
+
+#[test]
+fn fsync() {
+    initialize();
+    let source = COPIES_DIR.join("fsync_source");
+    let destination = COPIES_DIR.join("fsync");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    std::fs::write(source.join("directory/file"), "contents").unwrap();
+    fs::set_permissions(
+        source.join("directory/file"),
+        PermissionsExt::from_mode(0o200),
+    )
+    .unwrap();
+    fs::symlink("directory/file", source.join("symlink")).unwrap();
+    for options in [&["--fsync"][..], &["--fsync", "--atomic"]] {
+        remove(&destination);
+        let mut args: Vec<&OsStr> = options.iter().map(OsStr::new).collect();
+        args.extend([source.as_os_str(), destination.as_os_str()]);
+        let result = fcp_run(&args);
+        assert!(result.success);
+        assert_eq!(result.stderr, "");
+        assert_eq!(
+            std::fs::read_to_string(destination.join("directory/file")).unwrap(),
+            "contents"
+        );
+        assert_eq!(
+            fs::read_link(destination.join("symlink")).unwrap(),
+            Path::new("directory/file")
+        );
+    }
+}
+
+#[test]
+fn directory_timestamps() {
+    initialize();
+    let source = COPIES_DIR.join("directory_timestamps_source");
+    let destination = COPIES_DIR.join("directory_timestamps");
+    remove(&source);
+    remove(&destination);
+    let directories = ["", "a", "a/b", "a/b/c", "d"];
+    for directory in &directories {
+        fs::create_dir(source.join(directory), 0o777).unwrap();
+    }
+    for directory in &directories[1..] {
+        std::fs::write(source.join(directory).join("file"), "contents").unwrap();
+        fs::symlink("file", source.join(directory).join("symlink")).unwrap();
+    }
+    // Deepest first, as touching each directory's entries updates its own modification time
+    for directory in directories.iter().rev() {
+        touch(&source.join(directory), "2001-02-03 04:05:06.789012345");
+    }
+    for options in [&["-a"][..], &["-a", "-j4"], &["-a", "--atomic"]] {
+        remove(&destination);
+        let mut args: Vec<&OsStr> = options.iter().map(OsStr::new).collect();
+        args.extend([source.as_os_str(), destination.as_os_str()]);
+        assert!(fcp_run(&args).success);
+        for directory in &directories {
+            let (expected, actual) = (
+                fs::symlink_metadata(source.join(directory)).unwrap(),
+                fs::symlink_metadata(destination.join(directory)).unwrap(),
+            );
+            assert_eq!(
+                (actual.mtime(), actual.mtime_nsec()),
+                (expected.mtime(), expected.mtime_nsec()),
+                "{:?} with {:?}",
+                directory,
+                options
+            );
+        }
+    }
+}
+
+#[test]
+fn rewrite_symlinks() {
+    initialize();
+    let source = COPIES_DIR.join("rewrite_symlinks_source");
+    let destination = COPIES_DIR.join("rewrite_symlinks");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    std::fs::write(source.join("directory/file"), "contents").unwrap();
+    let absolute = std::env::current_dir().unwrap().join(&source);
+    let links = [
+        ("absolute", absolute.join("directory/file"), "file"),
+        ("roundabout", "../directory/./file".into(), "file"),
+        ("root", absolute.clone(), ".."),
+        ("outside", "../../outside".into(), "../../outside"),
+        ("absolute_outside", "/etc/hosts".into(), "/etc/hosts"),
+    ];
+    for (name, target, _) in &links {
+        fs::symlink(target, source.join("directory").join(name)).unwrap();
+    }
+    let result = fcp_run(&[
+        OsStr::new("--rewrite-symlinks=relative"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    for (name, _, expected) in &links {
+        let link = destination.join("directory").join(name);
+        assert_eq!(fs::read_link(link).unwrap(), Path::new(expected));
+    }
+    assert_eq!(
+        std::fs::read_to_string(destination.join("directory/absolute")).unwrap(),
+        "contents"
+    );
+}
+
+#[test]
+fn symlink_target() {
+    initialize();
+    let source = COPIES_DIR.join("symlink_target_source");
+    let destination = COPIES_DIR.join("symlink_target");
+    remove(&source);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    fs::symlink("/etc/hosts", source.join("directory/absolute")).unwrap();
+    fs::symlink("../directory/../file", source.join("directory/relative")).unwrap();
+    let read_link = |name| fs::read_link(destination.join("directory").join(name)).unwrap();
+    let copy = |how| {
+        remove(&destination);
+        let option = format!("--symlink-target={}", how);
+        let result = fcp_run(&[
+            OsStr::new(&option),
+            source.as_os_str(),
+            destination.as_os_str(),
+        ]);
+        assert!(result.success);
+    };
+    copy("keep");
+    assert_eq!(read_link("absolute"), Path::new("/etc/hosts"));
+    assert_eq!(read_link("relative"), Path::new("../directory/../file"));
+    copy("relative");
+    assert!(read_link("absolute").is_relative());
+    assert_eq!(
+        std::fs::canonicalize(destination.join("directory/absolute")).unwrap(),
+        std::fs::canonicalize("/etc/hosts").unwrap()
+    );
+    assert_eq!(read_link("relative"), Path::new("../directory/../file"));
+    copy("absolute");
+    assert_eq!(read_link("absolute"), Path::new("/etc/hosts"));
+    assert_eq!(
+        read_link("relative"),
+        std::env::current_dir()
+            .unwrap()
+            .join(&destination)
+            .join("file")
+    );
+    let result = fcp_run(&["--symlink-target=bogus", "a", "b"]);
+    assert_eq!(result.code, Some(2));
+}
+
+#[test]
+fn skip_existing() {
+    initialize();
+    let source = COPIES_DIR.join("skip_existing_source");
+    let destination = COPIES_DIR.join("skip_existing");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    std::fs::write(source.join("existing"), "new contents").unwrap();
+    std::fs::write(source.join("new"), "new contents").unwrap();
+    std::fs::write(destination.join("existing"), "old contents").unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--skip-existing"),
+        OsStr::new("-T"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(
+        result.stdout,
+        format!(
+            "skipped '{}' (exists)\n",
+            destination.join("existing").display()
+        )
+    );
+    assert_eq!(
+        std::fs::read_to_string(destination.join("existing")).unwrap(),
+        "old contents"
+    );
+    assert!(destination.join("new").exists());
+    let result = fcp_run(&[
+        OsStr::new("--skip-existing"),
+        OsStr::new("--output=json"),
+        OsStr::new("-T"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    let summary = result.stdout.lines().last().unwrap();
+    assert!(summary.contains(r#""skipped":2"#));
+}
+
+#[test]
+fn trailing_slash() {
+    initialize();
+    let source = COPIES_DIR.join("trailing_slash_source");
+    let destination = COPIES_DIR.join("trailing_slash");
+    remove(&source);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    let mut with_slash = source.clone().into_os_string();
+    with_slash.push("/");
+    // Without a trailing slash, the directory itself is copied into the destination
+    remove(&destination);
+    fs::create_dir(&destination, 0o777).unwrap();
+    assert!(fcp_run(&[source.as_os_str(), destination.as_os_str()]).success);
+    assert!(destination.join("trailing_slash_source/file").exists());
+    assert!(!destination.join("file").exists());
+    // With one, its contents are
+    remove(&destination);
+    fs::create_dir(&destination, 0o777).unwrap();
+    assert!(fcp_run(&[&with_slash, destination.as_os_str()]).success);
+    assert!(destination.join("file").exists());
+    assert!(!destination.join("trailing_slash_source").exists());
+    // Which makes no difference when the destination doesn't exist yet
+    remove(&destination);
+    assert!(fcp_run(&[&with_slash, destination.as_os_str()]).success);
+    assert!(destination.join("file").exists());
+    // A source which isn't a directory can't be given with one at all
+    remove(&destination);
+    fs::create_dir(&destination, 0o777).unwrap();
+    let mut file_with_slash = source.join("file").into_os_string();
+    file_with_slash.push("/");
+    assert!(!fcp_run(&[&file_with_slash, destination.as_os_str()]).success);
+}
+
+#[test]
+fn multiple_sources() {
+    initialize();
+    let source = COPIES_DIR.join("multiple_sources_source");
+    let destination = COPIES_DIR.join("multiple_sources");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("c"), 0o777).unwrap();
+    for file in ["a", "b", "c/d"] {
+        std::fs::write(source.join(file), file).unwrap();
+    }
+    let sources: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|name| source.join(name))
+        .collect();
+    let run = |destination: &Path| {
+        let mut args: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        args.push(destination);
+        fcp_run(&args)
+    };
+    // The destination must be an existing directory
+    let result = run(&destination);
+    assert!(!result.success);
+    assert!(!destination.exists());
+    std::fs::write(&destination, "").unwrap();
+    let result = run(&destination);
+    assert!(!result.success);
+    assert!(result.stderr.contains("is not a directory"));
+    remove(&destination);
+    fs::create_dir(&destination, 0o777).unwrap();
+    assert!(run(&destination).success);
+    for file in ["a", "b", "c/d"] {
+        assert_eq!(
+            std::fs::read_to_string(destination.join(file)).unwrap(),
+            file
+        );
+    }
+}
+
+#[test]
+fn into_itself() {
+    initialize();
+    let source = COPIES_DIR.join("into_itself");
+    let link = COPIES_DIR.join("into_itself_link");
+    remove(&source);
+    remove(&link);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::symlink("into_itself", &link).unwrap();
+    for destination in [
+        source.join("sub"),
+        link.join("sub"),
+        link.join("sub/deeper"),
+    ] {
+        let result = fcp_run(&[source.as_os_str(), destination.as_os_str()]);
+        assert_eq!(result.code, Some(1));
+        assert!(result.stderr.contains("into itself"), "{}", result.stderr);
+        assert!(!source.join("sub").exists());
+    }
+    // Copying into an existing subdirectory would copy the source to beneath that subdirectory
+    fs::create_dir(source.join("sub"), 0o777).unwrap();
+    let result = fcp_run(&[source.as_os_str(), source.join("sub").as_os_str()]);
+    assert!(result.stderr.contains("into itself"));
+    assert!(!source.join("sub/into_itself").exists());
+    // A file can't be copied over itself either, even by way of a symlink
+    for destination in [source.join("file"), link.join("file")] {
+        let result = fcp_run(&[source.join("file").as_os_str(), destination.as_os_str()]);
+        assert!(result.stderr.contains("are the same file"));
+        assert_eq!(
+            std::fs::read_to_string(source.join("file")).unwrap(),
+            "contents"
+        );
+    }
+    // Whereas a symlink to the source isn't descended into, so it can be copied into it
+    let result = fcp_run(&[link.as_os_str(), source.as_os_str()]);
+    assert!(result.success);
+    assert_eq!(
+        fs::read_link(source.join("into_itself_link")).unwrap(),
+        Path::new("into_itself")
+    );
+}
+
+#[test]
+fn mmap() {
+    initialize();
+    let source = COPIES_DIR.join("mmap_source");
+    let destination = COPIES_DIR.join("mmap");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    let contents: Vec<u8> = (0..5 << 20).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(source.join("file"), &contents).unwrap();
+    std::fs::write(source.join("empty"), "").unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--mmap"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert_eq!(std::fs::read(destination.join("file")).unwrap(), contents);
+    assert_eq!(std::fs::read(destination.join("empty")).unwrap(), b"");
+}
+
+#[test]
+fn direct() {
+    initialize();
+    let source = COPIES_DIR.join("direct_source");
+    let destination = COPIES_DIR.join("direct");
+    remove(&source);
+    fs::create_dir(&source, 0o777).unwrap();
+    // Sizes either side of the block size, so that some files end in a partial block
+    let sizes = [0, 1, 4095, 4096, 4097, 3 << 20, (3 << 20) + 123];
+    for size in sizes {
+        let contents: Vec<u8> = (0..size).map(|i: u32| (i % 251) as u8).collect();
+        std::fs::write(source.join(size.to_string()), contents).unwrap();
+    }
+    for options in [&["--direct"][..], &["--direct", "--buffer-size=5000"]] {
+        remove(&destination);
+        let mut args: Vec<&OsStr> = options.iter().map(OsStr::new).collect();
+        args.extend([source.as_os_str(), destination.as_os_str()]);
+        let result = fcp_run(&args);
+        assert!(result.success, "{}", result.stderr);
+        for size in sizes {
+            assert_eq!(
+                std::fs::read(destination.join(size.to_string())).unwrap(),
+                std::fs::read(source.join(size.to_string())).unwrap(),
+                "{} with {:?}",
+                size,
+                options
+            );
+        }
+    }
+}
+
+#[test]
+fn fadvise() {
+    initialize();
+    let source = COPIES_DIR.join("fadvise_source");
+    let destination = COPIES_DIR.join("fadvise");
+    remove(&source);
+    remove(&destination);
+    let contents: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(&source, &contents).unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--fadvise"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+}
+
+#[test]
+fn preallocate() {
+    initialize();
+    let source = COPIES_DIR.join("preallocate_source");
+    let destination = COPIES_DIR.join("preallocate");
+    remove(&source);
+    remove(&destination);
+    let contents: Vec<u8> = (0..3 << 19).map(|i: u32| (i % 251) as u8).collect();
+    std::fs::write(&source, &contents).unwrap();
+    for when in &["--preallocate", "--preallocate=never", "--preallocate=auto"] {
+        remove(&destination);
+        let result = fcp_run(&[
+            OsStr::new(when),
+            source.as_os_str(),
+            destination.as_os_str(),
+        ]);
+        assert!(result.success);
+        assert_eq!(result.stderr, "");
+        // Space allocated beyond the end of the contents mustn't change the size of the copy
+        assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    }
+    let result = fcp_run(&[
+        OsStr::new("--preallocate=sometimes"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert_eq!(
+        result.stderr,
+        "invalid argument 'sometimes' for '--preallocate'\n"
+    );
+}
+
+#[test]
+fn link() {
+    initialize();
+    let source = COPIES_DIR.join("link_source");
+    let destination = COPIES_DIR.join("link");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    for name in &["file", "directory/file"] {
+        fs::create(source.join(name), 0o644).unwrap();
+    }
+    fs::symlink("file", source.join("symlink")).unwrap();
+    let result = fcp_run(&[
+        "--link",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let inode =
+        |directory: &Path, name: &str| fs::symlink_metadata(directory.join(name)).unwrap().ino();
+    for name in &["file", "directory/file"] {
+        assert_eq!(inode(&source, name), inode(&destination, name));
+    }
+    // Directories and symlinks are created anew
+    assert_ne!(
+        inode(&source, "directory"),
+        inode(&destination, "directory")
+    );
+    assert_ne!(inode(&source, "symlink"), inode(&destination, "symlink"));
+    assert_eq!(
+        fs::read_link(destination.join("symlink")).unwrap(),
+        Path::new("file")
+    );
+}
+
+#[test]
+fn symbolic_link() {
+    initialize();
+    let source = COPIES_DIR.join("symbolic_link_source");
+    let destination = COPIES_DIR.join("symbolic_link");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    fs::create(source.join("directory/file"), 0o644).unwrap();
+    let result = fcp_run(&[
+        "-s",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert!(fs::symlink_metadata(destination.join("directory"))
+        .unwrap()
+        .is_dir());
+    let target = fs::read_link(destination.join("directory/file")).unwrap();
+    assert!(target.is_absolute());
+    assert_eq!(
+        fs::canonicalize(target).unwrap(),
+        fs::canonicalize(source.join("directory/file")).unwrap()
+    );
+    remove(&destination);
+    let result = fcp_run(&[
+        "-s",
+        "--relative",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert_eq!(
+        fs::read_link(destination.join("directory/file")).unwrap(),
+        Path::new("../../symbolic_link_source/directory/file")
+    );
+}
+
+#[test]
+fn compare_content() {
+    initialize();
+    let source = COPIES_DIR.join("compare_content_source");
+    let destination = COPIES_DIR.join("compare_content");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    for name in &["same", "different", "longer"] {
+        std::fs::write(source.join(name), "contents").unwrap();
+    }
+    std::fs::write(destination.join("same"), "contents").unwrap();
+    std::fs::write(destination.join("different"), "Contents").unwrap();
+    std::fs::write(destination.join("longer"), "contents, and more").unwrap();
+    let same = fs::symlink_metadata(destination.join("same"))
+        .unwrap()
+        .ino();
+    let result = fcp_run(&[
+        "--compare-content",
+        "--output=json",
+        "-T",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    assert!(result.stdout.contains(r#""skipped":1,"identical":1"#));
+    for name in &["same", "different", "longer"] {
+        assert_eq!(
+            std::fs::read_to_string(destination.join(name)).unwrap(),
+            "contents"
+        );
+    }
+    assert_eq!(
+        fs::symlink_metadata(destination.join("same"))
+            .unwrap()
+            .ino(),
+        same
+    );
+}
+
+#[test]
+fn attributes_only() {
+    initialize();
+    let source = COPIES_DIR.join("attributes_only_source");
+    let destination = COPIES_DIR.join("attributes_only");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o755).unwrap();
+    fs::create_dir(&destination, 0o700).unwrap();
+    std::fs::write(source.join("file"), "source").unwrap();
+    std::fs::write(destination.join("file"), "destination").unwrap();
+    fs::set_permissions(source.join("file"), PermissionsExt::from_mode(0o640)).unwrap();
+    touch(&source.join("file"), "2000-01-01 00:00:00");
+    std::fs::write(source.join("missing"), "").unwrap();
+    let result = fcp_run(&[
+        "--attributes-only",
+        "--preserve=mode,timestamps",
+        "-T",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("missing"));
+    assert!(fs::symlink_metadata(destination.join("missing")).is_err());
+    let file = destination.join("file");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "destination");
+    let metadata = fs::symlink_metadata(&file).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+    assert_eq!(
+        metadata.modified().unwrap(),
+        fs::symlink_metadata(source.join("file"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    );
+    // The mode of the existing directory is updated too
+    let mode = |path: &Path| fs::symlink_metadata(path).unwrap().permissions().mode();
+    assert_eq!(mode(&destination), mode(&source));
+}
+
+#[test]
+fn max_inflight() {
+    initialize();
+    let source = COPIES_DIR.join("max_inflight_source");
+    let destination = COPIES_DIR.join("max_inflight");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    // The limit is shared by directories copied concurrently, each of which still makes progress
+    // once it's been reached
+    let directories = [
+        "directory0",
+        "directory1",
+        "directory2",
+        "directory0/nested",
+    ];
+    for directory in &directories {
+        fs::create_dir(source.join(directory), 0o777).unwrap();
+    }
+    for i in 0..10 {
+        std::fs::write(source.join(format!("file{}", i)), i.to_string()).unwrap();
+        for directory in &directories {
+            let path = source.join(directory).join(format!("file{}", i));
+            std::fs::write(path, i.to_string()).unwrap();
+        }
+    }
+    let result = fcp_run(&[
+        "--max-inflight=3",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    for i in 0..10 {
+        let name = format!("file{}", i);
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(destination.join(&name)), i.to_string());
+        for directory in &directories {
+            assert_eq!(read(destination.join(directory).join(&name)), i.to_string());
+        }
+    }
+    let result = fcp_run(&[
+        "--max-inflight=0",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(!result.success);
+    assert_eq!(result.stderr, "invalid number of entries '0'\n");
+}
+
+#[test]
+fn interrupt() {
+    initialize();
+    let source = COPIES_DIR.join("interrupt_source");
+    let destination = COPIES_DIR.join("interrupt");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, vec![0; 1 << 22]).unwrap();
+    // Limited to copying the file over several seconds, so that it's interrupted partway through
+    let mut child = Command::new(fcp_executable_path())
+        .args(["--bwlimit=512K", "--buffer-size=64K", "--quiet"])
+        .args([&source, &destination])
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::symlink_metadata(&destination).map_or(true, |metadata| metadata.len() == 0) {
+        assert!(Instant::now() < deadline, "the copy never started");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    assert!(fs::symlink_metadata(&destination).is_err());
+    // Except when resuming, in which case the partial copy is kept to be resumed from (of a source
+    // which isn't all zeros, as that's copied as a hole without delay)
+    std::fs::write(&source, vec![1; 1 << 22]).unwrap();
+    let mut child = Command::new(fcp_executable_path())
+        .args(["--bwlimit=512K", "--buffer-size=64K", "--quiet", "--resume"])
+        .args([&source, &destination])
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::symlink_metadata(&destination).map_or(true, |metadata| metadata.len() == 0) {
+        assert!(Instant::now() < deadline, "the copy never started");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    let partial = fs::symlink_metadata(&destination).unwrap();
+    assert!(partial.len() > 0 && partial.len() < 1 << 22);
+    assert_eq!(
+        partial.mtime(),
+        fs::symlink_metadata(&source).unwrap().mtime()
+    );
+    let result = fcp_run(&[
+        OsStr::new("--resume"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success, "{}", result.stderr);
+    assert!(std::fs::read(&destination).unwrap() == vec![1; 1 << 22]);
+}
+
+#[test]
+fn unwind() {
+    initialize();
+    let source = COPIES_DIR.join("unwind_source");
+    let destination = COPIES_DIR.join("unwind");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, vec![1; 1 << 16]).unwrap();
+    // Writing beyond the limit on the size of files fails partway through the copy (with the
+    // signal otherwise sent for doing so ignored)
+    let limited = |args: &[&OsStr]| {
+        Command::new("sh")
+            .args(["-c", "trap '' XFSZ; ulimit -f 8; exec \"$0\" \"$@\""])
+            .arg(fcp_executable_path())
+            .args(args)
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap()
+    };
+    let output = limited(&[source.as_os_str(), destination.as_os_str()]);
+    assert!(!output.status.success());
+    assert!(!output.stderr.is_empty());
+    assert!(fs::symlink_metadata(&destination).is_err());
+    // Except when resuming, as the partial copy is what's resumed from
+    let output = limited(&[
+        OsStr::new("--resume"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert!(fs::symlink_metadata(&destination).unwrap().len() < 1 << 16);
+    let result = fcp_run(&[
+        OsStr::new("--resume"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(std::fs::read(&destination).unwrap(), vec![1; 1 << 16]);
+}
+
+#[test]
+fn log_file() {
+    initialize();
+    let source = COPIES_DIR.join("log_file_source");
+    let destination = COPIES_DIR.join("log_file");
+    let log = COPIES_DIR.join("log_file.log");
+    remove(&source);
+    remove(&destination);
+    remove(&log);
+    std::fs::write(&source, "contents").unwrap();
+    let args = [
+        OsString::from("--log-file"),
+        log.clone().into_os_string(),
+        OsString::from("--no-clobber"),
+        source.clone().into_os_string(),
+        destination.clone().into_os_string(),
+    ];
+    // Logged regardless of what's printed on stdout, and appended to by each run
+    for _ in 0..2 {
+        let result = fcp_run(&args);
+        assert!(result.success);
+        assert_eq!(result.stdout, "");
+    }
+    let log = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 4);
+    for line in &lines {
+        assert!(line.starts_with(r#"{"time":"#));
+    }
+    assert!(lines[0].ends_with(&format!(
+        r#""op":"copy","src":"{}","dst":"{}","bytes":8}}"#,
+        source.display(),
+        destination.display()
+    )));
+    assert!(lines[1].contains(r#""op":"summary","files":1,"#));
+    assert!(lines[2].ends_with(&format!(
+        r#""op":"skip","dst":"{}"}}"#,
+        destination.display()
+    )));
+    assert!(lines[3].contains(r#""op":"summary","files":0,"#));
+    let result = fcp_run(&[
+        OsStr::new("--log-file"),
+        COPIES_DIR.join("log_file_missing/log").as_os_str(),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("log_file_missing/log"));
+}
+
+#[test]
+fn numeric_ids() {
+    initialize();
+    let source = COPIES_DIR.join("numeric_ids_source");
+    let destination = COPIES_DIR.join("numeric_ids");
+    remove(&source);
+    remove(&destination);
+    fs::create(&source, 0o644).unwrap();
+    // IDs with no names in the user and group databases, which can only be given as root
+    let chowned = Command::new("chown")
+        .arg("54321:54322")
+        .arg(&source)
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    if !chowned.success() {
+        return;
+    }
+    let result = fcp_run(&[
+        OsStr::new("--preserve=ownership"),
+        OsStr::new("--numeric-ids"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let metadata = fs::symlink_metadata(&destination).unwrap();
+    assert_eq!((metadata.uid(), metadata.gid()), (54321, 54322));
+}
+
+#[test]
+fn error_source() {
+    use std::error::Error;
+    initialize();
+    let missing = COPIES_DIR.join("error_source_missing");
+    let err = fs::read_link(&missing).unwrap_err();
+    let source = err
+        .source()
+        .unwrap()
+        .downcast_ref::<std::io::Error>()
+        .unwrap();
+    assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(err.raw_os_error(), source.raw_os_error());
+    // Causes other than I/O errors are retained too
+    let err = fs::mkfifo("error_source\0fifo", PermissionsExt::from_mode(0o644)).unwrap_err();
+    assert!(err.source().is_some());
+    assert_eq!(err.raw_os_error(), None);
+}
+
+#[test]
+fn retries() {
+    initialize();
+    let source = COPIES_DIR.join("retries_source");
+    let destination = COPIES_DIR.join("retries");
+    remove(&source);
+    remove(&destination);
+    // A missing source isn't going to appear by waiting for it, so it fails without any retries
+    let start = Instant::now();
+    let result = fcp_run(&[
+        OsStr::new("--retries=5"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert!(!result.stderr.contains("retrying"));
+    assert!(start.elapsed() < Duration::from_secs(3));
+    std::fs::write(&source, "contents").unwrap();
+    let result = fcp_run(&[
+        OsStr::new("--retries=5"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(std::fs::read_to_string(&destination).unwrap(), "contents");
+    let result = fcp_run(&[
+        OsStr::new("--retries=-1"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert_eq!(result.stderr, "invalid number of retries '-1'\n");
+}
+
+#[test]
+fn chmod() {
+    initialize();
+    let source = COPIES_DIR.join("chmod_source");
+    let destination = COPIES_DIR.join("chmod");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o700)).unwrap();
+    fs::create(source.join("file"), 0o666).unwrap();
+    fs::set_permissions(source.join("file"), PermissionsExt::from_mode(0o666)).unwrap();
+    let mode = |name: &str| {
+        fs::symlink_metadata(destination.join(name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777
+    };
+    for (expression, file, directory) in [
+        ("go-w", 0o644, 0o700),
+        ("u=rwx,g=rx,o=", 0o750, 0o750),
+        // Execute permission is only added to directories and files which are already executable
+        ("a+X", 0o666, 0o711),
+        ("600", 0o600, 0o600),
+        ("g+s,o-rw", 0o2660, 0o2700),
+    ] {
+        remove(&destination);
+        let result = fcp_run(&[
+            OsStr::new("--chmod"),
+            OsStr::new(expression),
+            source.as_os_str(),
+            destination.as_os_str(),
+        ]);
+        assert!(result.success, "{}", expression);
+        assert_eq!(result.stderr, "");
+        assert_eq!(mode("file"), file, "{}", expression);
+        assert_eq!(mode(""), directory, "{}", expression);
+    }
+    let result = fcp_run(&[
+        OsStr::new("--chmod=u+r,z+q"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(!result.success);
+    assert_eq!(result.stderr, "invalid mode 'u+r,z+q'\n");
+}
+
+#[test]
+fn parents() {
+    initialize();
+    let source = COPIES_DIR.join("parents_source");
+    let destination = COPIES_DIR.join("parents");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("a"), 0o777).unwrap();
+    fs::create_dir(source.join("a/b"), 0o777).unwrap();
+    fs::set_permissions(source.join("a"), PermissionsExt::from_mode(0o750)).unwrap();
+    fs::set_permissions(source.join("a/b"), PermissionsExt::from_mode(0o705)).unwrap();
+    std::fs::write(source.join("a/b/c.txt"), "contents").unwrap();
+    std::fs::write(source.join("a/d.txt"), "contents").unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    let result = fcp_run(&[
+        "--parents",
+        source.join("a/b/c.txt").to_str().unwrap(),
+        source.join("./a/d.txt").to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let copy = destination.join(&source);
+    for name in &["a/b/c.txt", "a/d.txt"] {
+        assert_eq!(
+            std::fs::read_to_string(copy.join(name)).unwrap(),
+            "contents"
+        );
+    }
+    for (name, mode) in &[("a", 0o750), ("a/b", 0o705)] {
+        assert_eq!(
+            fs::metadata(copy.join(name)).unwrap().permissions().mode() & 0o7777,
+            *mode
+        );
+    }
+    let result = fcp_run(&[
+        "--parents",
+        source.join("a/../a/d.txt").to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    assert!(!result.success);
+    assert!(result
+        .stderr
+        .ends_with(": cannot recreate a path containing '..' with '--parents'\n"));
+    let result = fcp_run(&[
+        "--parents",
+        source.join("a/d.txt").to_str().unwrap(),
+        destination.join("missing").to_str().unwrap(),
+    ]);
+    assert!(!result.success);
+    assert!(!destination.join("missing").exists());
+}
+
+#[test]
+fn reflink() {
+    initialize();
+    let source = COPIES_DIR.join("reflink_source");
+    let destination = COPIES_DIR.join("reflink");
+    remove(&source);
+    remove(&destination);
+    std::fs::write(&source, "contents").unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o640)).unwrap();
+    for when in &["auto", "never"] {
+        let result = fcp_run(&[
+            &format!("--reflink={}", when),
+            source.to_str().unwrap(),
+            destination.to_str().unwrap(),
+        ]);
+        assert!(result.success);
+        assert_eq!(std::fs::read_to_string(&destination).unwrap(), "contents");
+        assert_eq!(
+            fs::metadata(&destination).unwrap().permissions().mode() & 0o7777,
+            0o640
+        );
+        remove(&destination);
+    }
+    // Whether cloning is possible depends on the filesystem the fixtures are on
+    let result = fcp_run(&[
+        "--reflink=always",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap(),
+    ]);
+    if result.success {
+        assert_eq!(std::fs::read_to_string(&destination).unwrap(), "contents");
+    } else {
+        assert_eq!(
+            result.stderr,
+            format!(
+                "{}: failed to clone to {}\n",
+                source.display(),
+                destination.display()
+            )
+        );
+    }
+}
+
+#[test]
+fn file_type_display() {
+    initialize();
+    let source = COPIES_DIR.join("file_type_display_source");
+    remove(&source);
+    fs::create_dir(&source, 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    fs::symlink("file", source.join("symlink")).unwrap();
+    fs::mkfifo(source.join("fifo"), PermissionsExt::from_mode(0o666)).unwrap();
+    let _listener = UnixListener::bind(source.join("socket")).unwrap();
+    let types = [
+        (source.clone(), "directory", false),
+        (source.join("file"), "regular file", false),
+        (source.join("symlink"), "symbolic link", false),
+        (source.join("fifo"), "fifo", true),
+        (source.join("socket"), "socket", true),
+        (PathBuf::from("/dev/null"), "character device", true),
+    ];
+    for (path, name, special) in &types {
+        let file_type = fs::file_type(path).unwrap();
+        assert_eq!(file_type.to_string(), *name);
+        assert_eq!(file_type.is_special(), *special);
+    }
+}
+
+#[test]
+fn expand_tilde() {
+    initialize();
+    let home = std::env::current_dir()
+        .unwrap()
+        .join(COPIES_DIR.join("expand_tilde_home"));
+    remove(&home);
+    fs::create_dir(&home, 0o777).unwrap();
+    std::fs::write(home.join("file"), "contents").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(fcp_executable_path())
+            .args(args)
+            .env("HOME", &home)
+            .output()
+            .unwrap()
+    };
+    let result = run(&["--expand-tilde", "~/file", "~/copy"]);
+    assert!(result.status.success());
+    assert_eq!(
+        std::fs::read_to_string(home.join("copy")).unwrap(),
+        "contents"
+    );
+    // Only a leading '~' is expanded, and only when asked to be
+    let result = run(&["~/file", "~/literal"]);
+    assert!(!result.status.success());
+    assert!(!home.join("literal").exists());
+    let result = run(&["--expand-tilde", "~nonexistent_fcp_user/file", "~/copy"]);
+    assert!(!result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stderr).unwrap(),
+        "~nonexistent_fcp_user/file: no such user 'nonexistent_fcp_user'\n"
+    );
+}
+
+#[test]
+fn current_directory() {
+    initialize();
+    let cwd = std::env::current_dir().unwrap();
+    let source = cwd.join(COPIES_DIR.join("current_directory_source"));
+    let destination = cwd.join(COPIES_DIR.join("current_directory"));
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("b"), 0o777).unwrap();
+    std::fs::write(source.join("a"), "a").unwrap();
+    std::fs::write(source.join("b/file"), "b").unwrap();
+    std::fs::write(source.join("c"), "c").unwrap();
+    fs::create_dir(&destination, 0o777).unwrap();
+    let run = |args: &[PathBuf]| {
+        Command::new(fcp_executable_path())
+            .args(args)
+            .arg(".")
+            .current_dir(&destination)
+            .output()
+            .unwrap()
+    };
+    // A single source is copied into the directory under its own name
+    assert!(run(std::slice::from_ref(&source)).status.success());
+    for (name, contents) in &[("a", "a"), ("b/file", "b"), ("c", "c")] {
+        assert_eq!(
+            std::fs::read_to_string(destination.join("current_directory_source").join(name))
+                .unwrap(),
+            *contents
+        );
+    }
+    // As is each of several
+    let sources: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|name| source.join(name))
+        .collect();
+    assert!(run(&sources).status.success());
+    for (name, contents) in &[("a", "a"), ("b/file", "b"), ("c", "c")] {
+        assert_eq!(
+            std::fs::read_to_string(destination.join(name)).unwrap(),
+            *contents
+        );
+    }
+}
+
+#[test]
+fn read_only_directory() {
+    initialize();
+    let source = COPIES_DIR.join("read_only_directory_source");
+    let destination = COPIES_DIR.join("read_only_directory");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create_dir(source.join("directory"), 0o777).unwrap();
+    std::fs::write(source.join("file"), "contents").unwrap();
+    std::fs::write(source.join("directory/file"), "contents").unwrap();
+    for directory in [source.join("directory"), source.clone()] {
+        fs::set_permissions(directory, PermissionsExt::from_mode(0o555)).unwrap();
+    }
+    let result = fcp_run(&[source.as_os_str(), destination.as_os_str()]);
+    assert!(result.success, "{}", result.stderr);
+    let mode = |path: &Path| fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(&destination), 0o555);
+    assert_eq!(mode(&destination.join("directory")), 0o555);
+    for file in ["file", "directory/file"] {
+        assert_eq!(
+            std::fs::read_to_string(destination.join(file)).unwrap(),
+            "contents"
+        );
+    }
+}
+
+#[test]
+fn empty_directory_mode() {
+    initialize();
+    let source = COPIES_DIR.join("empty_directory_mode_source");
+    let destination = COPIES_DIR.join("empty_directory_mode");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    for (name, mode) in &[("private", 0o700), ("shared", 0o775), ("sticky", 0o1777)] {
+        fs::create_dir(source.join(name), 0o777).unwrap();
+        fs::set_permissions(source.join(name), PermissionsExt::from_mode(*mode)).unwrap();
+    }
+    for args in &[&[][..], &["-a"][..]] {
+        remove(&destination);
+        let mut args: Vec<&OsStr> = args.iter().map(OsStr::new).collect();
+        args.extend(&[source.as_os_str(), destination.as_os_str()]);
+        let result = fcp_run(&args);
+        assert!(result.success);
+        for (name, mode) in &[("private", 0o700), ("shared", 0o775), ("sticky", 0o1777)] {
+            let metadata = fs::symlink_metadata(destination.join(name)).unwrap();
+            assert!(metadata.is_dir());
+            assert_eq!(metadata.permissions().mode() & 0o7777, *mode);
+            assert_eq!(
+                std::fs::read_dir(destination.join(name)).unwrap().count(),
+                0
+            );
+        }
+    }
+}
+
+#[test]
+fn force_read_only() {
+    initialize();
+    let source = COPIES_DIR.join("force_read_only_source");
+    let destination = COPIES_DIR.join("force_read_only");
+    let link = COPIES_DIR.join("force_read_only_link");
+    remove(&source);
+    remove(&destination);
+    remove(&link);
+    std::fs::write(&source, "new").unwrap();
+    fs::set_permissions(&source, PermissionsExt::from_mode(0o640)).unwrap();
+    std::fs::write(&destination, "old").unwrap();
+    fs::set_permissions(&destination, PermissionsExt::from_mode(0o444)).unwrap();
+    std::fs::hard_link(&destination, &link).unwrap();
+    let result = fcp_run(&[Path::new("--force"), &source, &destination]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    // Overwritten in place, rather than replaced
+    assert_eq!(std::fs::read_to_string(&link).unwrap(), "new");
+    assert_eq!(
+        fs::metadata(&destination).unwrap().permissions().mode() & 0o7777,
+        0o640
+    );
+}
+
+#[test]
+fn deep_tree() {
+    initialize();
+    let source = COPIES_DIR.join("deep_tree_source");
+    let destination = COPIES_DIR.join("deep_tree");
+    remove(&source);
+    remove(&destination);
+    let nested = |root: &Path, depth| -> PathBuf {
+        (0..depth).fold(root.to_path_buf(), |path, _| path.join("d"))
+    };
+    std::fs::create_dir_all(nested(&source, 1000)).unwrap();
+    std::fs::write(nested(&source, 1000).join("file"), "contents").unwrap();
+    for depth in (0..=1000).step_by(100) {
+        touch(&nested(&source, depth), "2000-01-01 00:00:00");
+    }
+    // With a single worker, whose stack is smaller than that of the main thread
+    let result = fcp_run(&[
+        "-a".as_ref(),
+        "-j1".as_ref(),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(
+        std::fs::read_to_string(nested(&destination, 1000).join("file")).unwrap(),
+        "contents"
+    );
+    // The modification time of each directory is preserved once every entry within it is copied
+    for depth in (0..=1000).step_by(100) {
+        assert_eq!(
+            fs::metadata(nested(&destination, depth)).unwrap().mtime(),
+            fs::metadata(nested(&source, depth)).unwrap().mtime()
+        );
+    }
+}
+
+#[test]
+fn symlink_metadata() {
+    initialize();
+    let source = COPIES_DIR.join("symlink_metadata_source");
+    let destination = COPIES_DIR.join("symlink_metadata");
+    remove(&source);
+    remove(&destination);
+    fs::create_dir(&source, 0o777).unwrap();
+    fs::create(source.join("target"), 0o644).unwrap();
+    fs::symlink("target", source.join("link")).unwrap();
+    touch(&source.join("link"), "2000-01-01 00:00:00");
+    // The link is given an owner distinct from that of its target, which can only be done as root
+    let chowned = Command::new("chown")
+        .args(["-h", "54321:54322"])
+        .arg(source.join("link"))
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    if !chowned.success() {
+        return;
+    }
+    let result = fcp_run(&[
+        OsStr::new("-a"),
+        source.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    assert!(result.success);
+    assert_eq!(result.stderr, "");
+    let source_metadata = fs::symlink_metadata(source.join("link")).unwrap();
+    let metadata = fs::symlink_metadata(destination.join("link")).unwrap();
+    assert!(metadata.file_type().is_symlink());
+    assert_eq!((metadata.uid(), metadata.gid()), (54321, 54322));
+    assert_eq!(metadata.mtime(), source_metadata.mtime());
+    // The target keeps its own owner
+    let target = fs::symlink_metadata(destination.join("target")).unwrap();
+    assert_ne!((target.uid(), target.gid()), (54321, 54322));
+}
+
+#[test]
+fn copy_device_contents() {
+    initialize();
+    let image = COPIES_DIR.join("copy_device_contents_image");
+    let destination = COPIES_DIR.join("copy_device_contents");
+    let node = COPIES_DIR.join("copy_device_contents_node");
+    remove(&image);
+    remove(&destination);
+    remove(&node);
+    let contents: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&image, &contents).unwrap();
+    // Block devices can only be attached to images as root, and only where loop devices exist
+    let attached = Command::new("losetup")
+        .args(["--find", "--show"])
+        .arg(&image)
+        .stderr(Stdio::null())
+        .output();
+    let device = match attached {
+        Ok(output) if output.status.success() => {
+            PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end())
+        }
+        _ => return,
+    };
+    let copied = fcp_run(&[
+        OsStr::new("--copy-device-contents"),
+        OsStr::new("--preserve=special"),
+        device.as_os_str(),
+        destination.as_os_str(),
+    ]);
+    let recreated = fcp_run(&[
+        OsStr::new("--preserve=special"),
+        device.as_os_str(),
+        node.as_os_str(),
+    ]);
+    Command::new("losetup")
+        .arg("--detach")
+        .arg(&device)
+        .status()
+        .unwrap();
+    assert!(copied.success);
+    assert_eq!(copied.stderr, "");
+    assert!(fs::symlink_metadata(&destination).unwrap().is_file());
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    assert!(recreated.success);
+    assert!(fs::symlink_metadata(&node)
+        .unwrap()
+        .file_type()
+        .is_block_device());
+}